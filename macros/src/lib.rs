@@ -0,0 +1,207 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    meta::ParseNestedMeta, parse_macro_input, Data, DeriveInput, Error, Fields, LitStr, Token,
+};
+
+#[derive(Default)]
+struct SerdeField {
+    rename: Option<String>,
+    skip: bool,
+}
+
+// Consumes the `= value` or `(...)` after an attribute we do not interpret.
+fn skip_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<syn::Lit>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|nested| skip_value(&nested))?;
+    }
+    Ok(())
+}
+
+// Reads the `#[serde(...)]` attributes that change or drop the stored field name,
+// ignoring everything else.
+fn serde_field(attrs: &[syn::Attribute]) -> syn::Result<SerdeField> {
+    let mut field = SerdeField::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                if !meta.input.peek(Token![=]) {
+                    // The stored name is the serialized one, which may differ from
+                    // the one read back, so there is no single name to use.
+                    return Err(meta.error(
+                        "ModelFields does not support #[serde(rename(...))], use #[serde(rename = \"...\")]",
+                    ));
+                }
+                field.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("flatten") {
+                field.skip = true;
+            } else {
+                skip_value(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(field)
+}
+
+fn has_rename_all(attrs: &[syn::Attribute]) -> syn::Result<bool> {
+    let mut rename_all = false;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            rename_all |= meta.path.is_ident("rename_all");
+            skip_value(&meta)
+        })?;
+    }
+    Ok(rename_all)
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "ModelFields only supports structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "ModelFields needs named fields",
+        ));
+    };
+    if has_rename_all(&input.attrs)? {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "ModelFields does not support #[serde(rename_all)]",
+        ));
+    }
+    let mut constants = Vec::new();
+    for field in &fields.named {
+        let serde = serde_field(&field.attrs)?;
+        if serde.skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().expect("named field");
+        let field_name = ident.to_string();
+        let field_name = field_name.trim_start_matches("r#");
+        let stored_name = serde.rename.unwrap_or_else(|| field_name.to_string());
+        let constant = format_ident!("{}", field_name.to_uppercase());
+        let ty = &field.ty;
+        constants.push(quote! {
+            pub const #constant: crate::database::query::Field<Self, #ty> =
+                crate::database::query::Field::new(#stored_name);
+        });
+    }
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #type_generics #where_clause {
+            #(#constants)*
+        }
+    })
+}
+
+// Generates one typed `Field` constant per stored field, named after the field in
+// upper case and carrying the name serde stores it under, e.g. `UserModel::ID`
+// for `#[serde(rename = "_id")] id: String`.
+#[proc_macro_derive(ModelFields)]
+pub fn derive_model_fields(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn constants(input: DeriveInput) -> String {
+        expand(input).unwrap().to_string()
+    }
+
+    fn error(input: DeriveInput) -> String {
+        expand(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn uses_the_serde_name() {
+        let expanded = constants(parse_quote! {
+            struct Model {
+                #[serde(rename = "_id")]
+                id: String,
+                #[serde(default, with = "serde_bytes")]
+                r#type: Vec<u8>,
+                email: Option<String>,
+            }
+        });
+        assert!(expanded
+            .contains("pub const ID : crate :: database :: query :: Field < Self , String >"));
+        assert!(expanded.contains("Field :: new (\"_id\")"));
+        assert!(expanded.contains("pub const TYPE"));
+        assert!(expanded.contains("Field :: new (\"type\")"));
+        assert!(expanded.contains(
+            "pub const EMAIL : crate :: database :: query :: Field < Self , Option < String > >"
+        ));
+        assert!(expanded.contains("Field :: new (\"email\")"));
+    }
+
+    #[test]
+    fn leaves_out_skipped_and_flattened_fields() {
+        let expanded = constants(parse_quote! {
+            struct Model {
+                id: String,
+                #[serde(skip)]
+                cache: String,
+                #[serde(flatten)]
+                extra: Document,
+            }
+        });
+        assert!(expanded.contains("pub const ID"));
+        assert!(!expanded.contains("CACHE"));
+        assert!(!expanded.contains("EXTRA"));
+    }
+
+    #[test]
+    fn rejects_rename_all() {
+        let message = error(parse_quote! {
+            #[serde(rename_all = "camelCase")]
+            struct Model {
+                first_name: String,
+            }
+        });
+        assert_eq!(message, "ModelFields does not support #[serde(rename_all)]");
+        let message = error(parse_quote! {
+            #[serde(rename_all(serialize = "camelCase"))]
+            struct Model {
+                first_name: String,
+            }
+        });
+        assert_eq!(message, "ModelFields does not support #[serde(rename_all)]");
+    }
+
+    #[test]
+    fn rejects_split_rename() {
+        let message = error(parse_quote! {
+            struct Model {
+                #[serde(rename(serialize = "_id"))]
+                id: String,
+            }
+        });
+        assert!(message.starts_with("ModelFields does not support #[serde(rename(...))]"));
+    }
+
+    #[test]
+    fn rejects_enums_and_tuple_structs() {
+        assert_eq!(
+            error(parse_quote! { enum Model { A } }),
+            "ModelFields only supports structs"
+        );
+        assert_eq!(
+            error(parse_quote! { struct Model(String); }),
+            "ModelFields needs named fields"
+        );
+    }
+}
@@ -1,12 +1,822 @@
+use crate::{handlers::error_handler::Errors, helpers::enums::OAuthType};
+use ::config::{Config, Environment, File};
+use actix_web::http::{header::HeaderName, Method};
 use dotenv::dotenv;
-use lazy_static::lazy_static;
-use std::env;
+use jsonwebtoken::Algorithm;
+use mongodb::options::{
+    Acknowledgment, Compressor, ReadConcern, ReadPreference, TlsOptions, WriteConcern,
+};
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fmt,
+    marker::PhantomData,
+    path::PathBuf,
+    str::FromStr,
+    sync::OnceLock,
+};
 
-pub fn load_env() {
-    dotenv().ok();
+static APP_CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+// Environment variables are `APP_<SECTION>__<KEY>`, e.g. `APP_MONGO__URI`.
+const ENV_PREFIX: &str = "APP";
+const ENV_SEPARATOR: &str = "__";
+// HMAC-SHA256 keys shorter than the digest weaken the cursor and token signatures.
+const MIN_SECRET_LENGTH: usize = 32;
+
+// Development relaxes CORS and exposes internal error messages, so it has to be
+// asked for explicitly.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    Development,
+    #[default]
+    Production,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    // Defaults to one worker per physical core when unset.
+    pub workers: Option<usize>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            workers: None,
+        }
+    }
+}
+
+// Unset options keep the driver default or the value from the connection string.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MongoConfig {
+    pub uri: String,
+    pub database: String,
+    pub app_name: Option<String>,
+    pub max_pool_size: Option<u32>,
+    pub min_pool_size: Option<u32>,
+    pub max_idle_time_ms: Option<u64>,
+    pub connect_timeout_ms: Option<u64>,
+    pub server_selection_timeout_ms: Option<u64>,
+    // local, available, majority, linearizable or snapshot
+    pub read_concern: Option<String>,
+    // majority, a node count or a tag set name
+    pub write_concern: Option<String>,
+    // primary, primaryPreferred, secondary, secondaryPreferred or nearest
+    pub read_preference: Option<String>,
+    // zstd, zlib or snappy, in order of preference
+    #[serde(default)]
+    pub compressors: Vec<String>,
+    pub tls_ca_file: Option<String>,
+    pub tls_cert_key_file: Option<String>,
+}
+
+impl MongoConfig {
+    pub fn read_concern(&self) -> Result<Option<ReadConcern>, String> {
+        let Some(level) = self.read_concern.as_deref() else {
+            return Ok(None);
+        };
+        match level {
+            "local" => Ok(Some(ReadConcern::local())),
+            "available" => Ok(Some(ReadConcern::available())),
+            "majority" => Ok(Some(ReadConcern::majority())),
+            "linearizable" => Ok(Some(ReadConcern::linearizable())),
+            "snapshot" => Ok(Some(ReadConcern::snapshot())),
+            _ => Err(format!("mongo.read_concern {} is invalid", level)),
+        }
+    }
+
+    pub fn write_concern(&self) -> Option<WriteConcern> {
+        self.write_concern.as_ref().map(|w| {
+            WriteConcern::builder()
+                .w(Acknowledgment::from(w.clone()))
+                .build()
+        })
+    }
+
+    pub fn read_preference(&self) -> Result<Option<ReadPreference>, String> {
+        let Some(mode) = self.read_preference.as_deref() else {
+            return Ok(None);
+        };
+        let options = Default::default();
+        match mode {
+            "primary" => Ok(Some(ReadPreference::Primary)),
+            "primaryPreferred" => Ok(Some(ReadPreference::PrimaryPreferred { options })),
+            "secondary" => Ok(Some(ReadPreference::Secondary { options })),
+            "secondaryPreferred" => Ok(Some(ReadPreference::SecondaryPreferred { options })),
+            "nearest" => Ok(Some(ReadPreference::Nearest { options })),
+            _ => Err(format!("mongo.read_preference {} is invalid", mode)),
+        }
+    }
+
+    pub fn compressors(&self) -> Result<Vec<Compressor>, String> {
+        self.compressors
+            .iter()
+            .map(|compressor| match compressor.as_str() {
+                "zstd" => Ok(Compressor::Zstd { level: None }),
+                "zlib" => Ok(Compressor::Zlib { level: None }),
+                "snappy" => Ok(Compressor::Snappy),
+                _ => Err(format!("mongo.compressors entry {} is invalid", compressor)),
+            })
+            .collect()
+    }
+
+    pub fn tls(&self) -> Option<TlsOptions> {
+        if self.tls_ca_file.is_none() && self.tls_cert_key_file.is_none() {
+            return None;
+        }
+        Some(
+            TlsOptions::builder()
+                .ca_file_path(self.tls_ca_file.as_ref().map(PathBuf::from))
+                .cert_key_file_path(self.tls_cert_key_file.as_ref().map(PathBuf::from))
+                .build(),
+        )
+    }
+}
+
+// A retired key tokens may still be verified with. `secret` is used for HMAC
+// algorithms, `public_key_path` for the others.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct VerificationKeyConfig {
+    pub kid: String,
+    pub algorithm: String,
+    pub secret: String,
+    pub public_key_path: String,
+}
+
+// Lists of tables can only come from the environment as indexed keys, e.g.
+// `APP_JWT__VERIFICATION_KEYS__0__KID`, which arrive as a map keyed by the index.
+fn indexed_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct IndexedList<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for IndexedList<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list or a map keyed by list index")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut items = Vec::new();
+            while let Some(item) = seq.next_element()? {
+                items.push(item);
+            }
+            Ok(items)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut items = BTreeMap::new();
+            while let Some(index) = map.next_key::<String>()? {
+                let index = index
+                    .parse::<usize>()
+                    .map_err(|_| de::Error::custom(format!("{} is not a list index", index)))?;
+                items.insert(index, map.next_value()?);
+            }
+            Ok(items.into_values().collect())
+        }
+    }
+
+    deserializer.deserialize_any(IndexedList(PhantomData))
 }
 
-lazy_static! {
-    pub static ref MONGO_URI: String = env::var("MONGO_URI").unwrap_or_default();
-    pub static ref DATABASE_NAME: String = env::var("DATABASE_NAME").unwrap_or_default();
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JwtConfig {
+    pub algorithm: String,
+    pub key_id: String,
+    pub secret: String,
+    pub private_key_path: String,
+    pub public_key_path: String,
+    // Retired keys still accepted for verification, `[[jwt.verification_keys]]` in files.
+    #[serde(default, deserialize_with = "indexed_list")]
+    pub verification_keys: Vec<VerificationKeyConfig>,
+    pub issuer: String,
+    pub audience: String,
+    pub leeway_seconds: u64,
+    pub access_token_ttl_seconds: i64,
+    pub refresh_token_ttl_seconds: i64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: "HS256".to_string(),
+            key_id: "default".to_string(),
+            secret: String::new(),
+            private_key_path: String::new(),
+            public_key_path: String::new(),
+            verification_keys: Vec::new(),
+            issuer: "actix-mongo-template".to_string(),
+            audience: "actix-mongo-template".to_string(),
+            leeway_seconds: 60,
+            access_token_ttl_seconds: 15 * 60,
+            refresh_token_ttl_seconds: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+// Origins are exact (`https://app.example.com`), wildcard subdomains
+// (`https://*.example.com`) or `*`. Headers accept `*` for any header.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CorsPolicy {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub exposed_headers: Vec<String>,
+    pub max_age: usize,
+    pub supports_credentials: bool,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: ["Authorization", "Content-Type"].map(String::from).to_vec(),
+            exposed_headers: Vec::new(),
+            max_age: 3600,
+            supports_credentials: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CorsConfig {
+    #[serde(flatten)]
+    pub policy: CorsPolicy,
+    // Replaces the policy for a route scope, keyed by scope name (`auth`, `users`).
+    #[serde(default)]
+    pub scopes: HashMap<String, CorsPolicy>,
+}
+
+impl CorsConfig {
+    pub fn policy(&self, scope: Option<&str>) -> &CorsPolicy {
+        scope
+            .and_then(|scope| self.scopes.get(scope))
+            .unwrap_or(&self.policy)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoggingConfig {
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ErrorsConfig {
+    // Emit RFC 7807 `application/problem+json` bodies.
+    pub problem_details: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HealthConfig {
+    // Adds what each dependency reports, versions and errors included, to
+    // `/health/ready`. Only for deployments where the endpoint is not public.
+    pub expose_details: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexSyncMode {
+    Apply,
+    // Only logs what would change. The default, so a deployment never builds or
+    // drops indexes unless it opts in or runs `sync-indexes`.
+    #[default]
+    DryRun,
+    Off,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IndexesConfig {
+    // What to do with the declared indexes of every model at startup.
+    pub sync: IndexSyncMode,
+    // Allows the sync to drop indexes that changed or are no longer declared.
+    // Without it those are only reported.
+    pub drop: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AuthConfig {
+    // Registering with this email grants the Admin role, to bootstrap a fresh database.
+    pub bootstrap_admin_email: String,
+    pub cursor_secret: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OAuthProviderConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub issuer: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+    // Lets a first login link to the existing account holding the same verified
+    // email, which trusts the provider to verify emails. Otherwise that login is
+    // rejected.
+    pub link_verified_email: bool,
+}
+
+impl OAuthProviderConfig {
+    // Endpoints default to the public provider so only the client credentials are required.
+    fn with_endpoints(endpoints: [&str; 4]) -> Self {
+        let [authorization_endpoint, token_endpoint, jwks_uri, issuer] = endpoints;
+        Self {
+            client_id: String::new(),
+            client_secret: String::new(),
+            authorization_endpoint: authorization_endpoint.to_string(),
+            token_endpoint: token_endpoint.to_string(),
+            jwks_uri: jwks_uri.to_string(),
+            issuer: issuer.to_string(),
+            redirect_uri: String::new(),
+            scopes: "openid email profile".to_string(),
+            link_verified_email: false,
+        }
+    }
+
+    // A provider without a client id is disabled.
+    pub fn is_enabled(&self) -> bool {
+        !self.client_id.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OAuthConfig {
+    pub google: OAuthProviderConfig,
+    pub facebook: OAuthProviderConfig,
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        Self {
+            google: OAuthProviderConfig::with_endpoints([
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                "https://oauth2.googleapis.com/token",
+                "https://www.googleapis.com/oauth2/v3/certs",
+                "https://accounts.google.com",
+            ]),
+            facebook: OAuthProviderConfig::with_endpoints([
+                "https://www.facebook.com/v18.0/dialog/oauth",
+                "https://graph.facebook.com/v18.0/oauth/access_token",
+                "https://www.facebook.com/.well-known/oauth/openid/jwks/",
+                "https://www.facebook.com",
+            ]),
+        }
+    }
+}
+
+impl OAuthConfig {
+    pub fn provider(&self, provider: OAuthType) -> Option<&OAuthProviderConfig> {
+        let config = match provider {
+            OAuthType::Google => &self.google,
+            OAuthType::Facebook => &self.facebook,
+            OAuthType::None => return None,
+        };
+        Some(config).filter(|config| config.is_enabled())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AppConfig {
+    pub profile: Profile,
+    pub server: ServerConfig,
+    pub mongo: MongoConfig,
+    pub jwt: JwtConfig,
+    pub cors: CorsConfig,
+    pub logging: LoggingConfig,
+    pub errors: ErrorsConfig,
+    pub health: HealthConfig,
+    pub indexes: IndexesConfig,
+    pub auth: AuthConfig,
+    pub oauth: OAuthConfig,
+}
+
+fn config_error(message: impl Into<String>) -> Errors {
+    Errors::InternalError(message.into())
+}
+
+fn is_hmac(algorithm: &str) -> bool {
+    matches!(
+        Algorithm::from_str(algorithm),
+        Ok(Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512)
+    )
+}
+
+// `*` may only stand for the leftmost labels of the host, as in `https://*.example.com`.
+fn is_valid_origin_wildcard(origin: &str) -> bool {
+    let host = origin.split_once("://").map_or(origin, |(_, host)| host);
+    host.strip_prefix("*.").is_some_and(|domain| {
+        !domain.is_empty() && !domain.starts_with('.') && !domain.contains(['*', '/'])
+    })
+}
+
+fn validate_cors_policy(
+    key: &str,
+    policy: &CorsPolicy,
+    profile: Profile,
+    errors: &mut Vec<String>,
+) {
+    for origin in &policy.allowed_origins {
+        if origin == "*" {
+            if policy.supports_credentials {
+                errors.push(format!(
+                    "{}.allowed_origins cannot contain * when supports_credentials is set",
+                    key
+                ));
+            } else if profile == Profile::Production {
+                errors.push(format!(
+                    "{}.allowed_origins cannot contain * in production",
+                    key
+                ));
+            }
+        } else if !origin.starts_with("http://") && !origin.starts_with("https://") {
+            errors.push(format!(
+                "{}.allowed_origins entry {} must start with http:// or https://",
+                key, origin
+            ));
+        } else if origin.contains('*') && !is_valid_origin_wildcard(origin) {
+            errors.push(format!(
+                "{}.allowed_origins entry {} may only use * as a whole leading label, e.g. https://*.example.com",
+                key, origin
+            ));
+        }
+    }
+    for method in &policy.allowed_methods {
+        if Method::from_str(method).is_err() {
+            errors.push(format!(
+                "{}.allowed_methods entry {} is invalid",
+                key, method
+            ));
+        }
+    }
+    for (name, headers) in [
+        ("allowed_headers", &policy.allowed_headers),
+        ("exposed_headers", &policy.exposed_headers),
+    ] {
+        for header in headers.iter().filter(|header| header.as_str() != "*") {
+            if HeaderName::from_str(header).is_err() {
+                errors.push(format!("{}.{} entry {} is invalid", key, name, header));
+            }
+        }
+    }
+}
+
+impl AppConfig {
+    fn environment() -> Environment {
+        Environment::with_prefix(ENV_PREFIX)
+            .prefix_separator("_")
+            .separator(ENV_SEPARATOR)
+            .try_parsing(true)
+            .list_separator(",")
+            .with_list_parse_key("mongo.compressors")
+            .with_list_parse_key("cors.allowed_origins")
+            .with_list_parse_key("cors.allowed_methods")
+            .with_list_parse_key("cors.allowed_headers")
+            .with_list_parse_key("cors.exposed_headers")
+    }
+
+    // Sources in increasing precedence: defaults, `config/default.{toml,yaml}`,
+    // `config/<profile>.{toml,yaml}` or `APP_CONFIG_FILE`, `.env`, then the environment.
+    // `.env` never overrides variables already set in the environment.
+    pub fn load() -> Result<Self, Errors> {
+        dotenv().ok();
+        let profile = env::var(format!("{}_PROFILE", ENV_PREFIX))
+            .unwrap_or_else(|_| "production".to_string());
+        let defaults =
+            Config::try_from(&Self::default()).map_err(|error| config_error(error.to_string()))?;
+        let mut builder = Config::builder()
+            .add_source(defaults)
+            .add_source(File::with_name("config/default").required(false))
+            .add_source(File::with_name(&format!("config/{}", profile)).required(false));
+        if let Ok(path) = env::var(format!("{}_CONFIG_FILE", ENV_PREFIX)) {
+            builder = builder.add_source(File::with_name(&path));
+        }
+        let config = builder
+            .add_source(Self::environment())
+            .build()
+            .and_then(Config::try_deserialize::<Self>)
+            .map_err(|error| config_error(format!("Invalid configuration: {}", error)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Collects every problem so a misconfigured deployment can be fixed in one pass.
+    pub fn validate(&self) -> Result<(), Errors> {
+        let mut errors = Vec::new();
+        let mut require = |value: &str, key: &str| {
+            if value.trim().is_empty() {
+                errors.push(format!("{} is required", key));
+            }
+        };
+        require(&self.server.host, "server.host");
+        require(&self.mongo.uri, "mongo.uri");
+        require(&self.mongo.database, "mongo.database");
+        require(&self.jwt.issuer, "jwt.issuer");
+        require(&self.jwt.audience, "jwt.audience");
+        require(&self.jwt.key_id, "jwt.key_id");
+        require(&self.auth.cursor_secret, "auth.cursor_secret");
+        require(&self.logging.level, "logging.level");
+        if is_hmac(&self.jwt.algorithm) {
+            require(&self.jwt.secret, "jwt.secret");
+        } else {
+            require(&self.jwt.private_key_path, "jwt.private_key_path");
+            require(&self.jwt.public_key_path, "jwt.public_key_path");
+        }
+        for (name, provider) in [
+            ("google", &self.oauth.google),
+            ("facebook", &self.oauth.facebook),
+        ] {
+            if provider.is_enabled() {
+                require(
+                    &provider.redirect_uri,
+                    &format!("oauth.{}.redirect_uri", name),
+                );
+            }
+        }
+
+        let mut require_length = |value: &str, key: &str| {
+            if !value.trim().is_empty() && value.len() < MIN_SECRET_LENGTH {
+                errors.push(format!(
+                    "{} must be at least {} bytes",
+                    key, MIN_SECRET_LENGTH
+                ));
+            }
+        };
+        require_length(&self.auth.cursor_secret, "auth.cursor_secret");
+        if is_hmac(&self.jwt.algorithm) {
+            require_length(&self.jwt.secret, "jwt.secret");
+        }
+        if self.server.port == 0 {
+            errors.push("server.port must be between 1 and 65535".to_string());
+        }
+        if self.server.workers == Some(0) {
+            errors.push("server.workers must be greater than 0".to_string());
+        }
+        if !self.mongo.uri.is_empty()
+            && !self.mongo.uri.starts_with("mongodb://")
+            && !self.mongo.uri.starts_with("mongodb+srv://")
+        {
+            errors.push("mongo.uri must start with mongodb:// or mongodb+srv://".to_string());
+        }
+        errors.extend(
+            [
+                self.mongo.read_concern().err(),
+                self.mongo.read_preference().err(),
+                self.mongo.compressors().err(),
+            ]
+            .into_iter()
+            .flatten(),
+        );
+        if let (Some(min), Some(max)) = (self.mongo.min_pool_size, self.mongo.max_pool_size) {
+            if min > max {
+                errors.push("mongo.min_pool_size cannot exceed mongo.max_pool_size".to_string());
+            }
+        }
+        if Algorithm::from_str(&self.jwt.algorithm).is_err() {
+            errors.push(format!(
+                "jwt.algorithm {} is not a supported algorithm",
+                self.jwt.algorithm
+            ));
+        }
+        for (index, key) in self.jwt.verification_keys.iter().enumerate() {
+            let name = format!("jwt.verification_keys.{}", index);
+            if key.kid.trim().is_empty() {
+                errors.push(format!("{}.kid is required", name));
+            }
+            if Algorithm::from_str(&key.algorithm).is_err() {
+                errors.push(format!(
+                    "{}.algorithm {} is not a supported algorithm",
+                    name, key.algorithm
+                ));
+            } else if is_hmac(&key.algorithm) && key.secret.is_empty() {
+                errors.push(format!("{}.secret is required", name));
+            } else if !is_hmac(&key.algorithm) && key.public_key_path.is_empty() {
+                errors.push(format!("{}.public_key_path is required", name));
+            }
+        }
+        if self.jwt.access_token_ttl_seconds <= 0 {
+            errors.push("jwt.access_token_ttl_seconds must be greater than 0".to_string());
+        }
+        if self.jwt.refresh_token_ttl_seconds <= 0 {
+            errors.push("jwt.refresh_token_ttl_seconds must be greater than 0".to_string());
+        }
+
+        let policies = std::iter::once(("cors".to_string(), &self.cors.policy)).chain(
+            self.cors
+                .scopes
+                .iter()
+                .map(|(scope, policy)| (format!("cors.scopes.{}", scope), policy)),
+        );
+        for (key, policy) in policies {
+            validate_cors_policy(&key, policy, self.profile, &mut errors);
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(config_error(format!(
+            "Invalid configuration:\n  - {}",
+            errors.join("\n  - ")
+        )))
+    }
+
+    pub fn init() -> Result<&'static Self, Errors> {
+        let config = Self::load()?;
+        APP_CONFIG
+            .set(config)
+            .map_err(|_| config_error("Configuration is already initialized"))?;
+        Ok(Self::get())
+    }
+
+    // The configuration is read through this global rather than `web::Data`, it is
+    // fixed at startup and also needed outside handlers, e.g. by `JwtToken::new`.
+    pub fn get() -> &'static Self {
+        APP_CONFIG.get().expect("Configuration is not initialized")
+    }
+
+    // Fixed configuration shared by the unit tests, which cannot read files or the environment.
+    #[cfg(test)]
+    pub fn init_for_tests() -> &'static Self {
+        APP_CONFIG.get_or_init(|| Self {
+            mongo: MongoConfig {
+                uri: "mongodb://localhost:27017".to_string(),
+                database: "test".to_string(),
+                ..Default::default()
+            },
+            jwt: JwtConfig {
+                secret: "test-jwt-secret-at-least-32-bytes".to_string(),
+                ..Default::default()
+            },
+            auth: AuthConfig {
+                bootstrap_admin_email: "admin@example.com".to_string(),
+                cursor_secret: "test-cursor-secret-at-least-32-bytes".to_string(),
+            },
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validation_error(config: &AppConfig) -> String {
+        config.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accepts_the_test_configuration() {
+        assert!(AppConfig::init_for_tests().validate().is_ok());
+    }
+
+    #[test]
+    fn requires_a_long_enough_cursor_secret() {
+        let mut config = AppConfig::init_for_tests().clone();
+        config.auth.cursor_secret = "  ".to_string();
+        assert!(validation_error(&config).contains("auth.cursor_secret is required"));
+        config.auth.cursor_secret = "short".to_string();
+        assert!(validation_error(&config).contains("auth.cursor_secret must be at least 32 bytes"));
+    }
+
+    #[test]
+    fn requires_a_long_enough_hmac_jwt_secret() {
+        let mut config = AppConfig::init_for_tests().clone();
+        config.jwt.secret = "short".to_string();
+        assert!(validation_error(&config).contains("jwt.secret must be at least 32 bytes"));
+        config.jwt.algorithm = "RS256".to_string();
+        config.jwt.private_key_path = "keys/private.pem".to_string();
+        config.jwt.public_key_path = "keys/public.pem".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_misplaced_origin_wildcards() {
+        let mut config = AppConfig::init_for_tests().clone();
+        config.cors.policy.allowed_origins = [
+            "https://*.example.com",
+            "http://*.example.com:8080",
+            "https://*example.com",
+            "https://app.*.example.com",
+            "https://*.*.example.com",
+            "https://*.",
+        ]
+        .map(String::from)
+        .to_vec();
+        let message = validation_error(&config);
+        for origin in ["https://*.example.com", "http://*.example.com:8080"] {
+            assert!(!message.contains(&format!("entry {} ", origin)));
+        }
+        for origin in [
+            "https://*example.com",
+            "https://app.*.example.com",
+            "https://*.*.example.com",
+            "https://*.",
+        ] {
+            assert!(message.contains(&format!(
+                "cors.allowed_origins entry {} may only use *",
+                origin
+            )));
+        }
+    }
+
+    fn load_from(source: impl ::config::Source + Send + Sync + 'static) -> AppConfig {
+        Config::builder()
+            .add_source(Config::try_from(&AppConfig::default()).unwrap())
+            .add_source(source)
+            .build()
+            .and_then(Config::try_deserialize::<AppConfig>)
+            .unwrap()
+    }
+
+    #[test]
+    fn defaults_to_the_production_profile() {
+        assert_eq!(AppConfig::default().profile, Profile::Production);
+    }
+
+    #[test]
+    fn reads_verification_keys_from_files() {
+        let config = load_from(File::from_str(
+            r#"
+            [[jwt.verification_keys]]
+            kid = "2023"
+            algorithm = "HS256"
+            secret = "with:colons,and,commas"
+
+            [[jwt.verification_keys]]
+            kid = "2024"
+            algorithm = "RS256"
+            public_key_path = "keys/2024.pem"
+            "#,
+            ::config::FileFormat::Toml,
+        ));
+        let keys = &config.jwt.verification_keys;
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].secret, "with:colons,and,commas");
+        assert_eq!(keys[1].public_key_path, "keys/2024.pem");
+    }
+
+    #[test]
+    fn reads_verification_keys_from_indexed_environment_variables() {
+        let variables = [
+            ("APP_JWT__VERIFICATION_KEYS__1__KID", "2024"),
+            ("APP_JWT__VERIFICATION_KEYS__1__ALGORITHM", "HS512"),
+            ("APP_JWT__VERIFICATION_KEYS__1__SECRET", "second"),
+            ("APP_JWT__VERIFICATION_KEYS__0__KID", "2023"),
+            ("APP_JWT__VERIFICATION_KEYS__0__ALGORITHM", "HS256"),
+            (
+                "APP_JWT__VERIFICATION_KEYS__0__SECRET",
+                "first:with,separators",
+            ),
+        ];
+        let config = load_from(
+            AppConfig::environment().source(Some(
+                variables
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            )),
+        );
+        let keys = &config.jwt.verification_keys;
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].kid, "2023");
+        assert_eq!(keys[0].secret, "first:with,separators");
+        assert_eq!(keys[1].kid, "2024");
+        assert_eq!(keys[1].algorithm, "HS512");
+    }
+
+    #[test]
+    fn validates_verification_keys() {
+        let mut config = AppConfig::init_for_tests().clone();
+        config.jwt.verification_keys = vec![VerificationKeyConfig {
+            kid: "2023".to_string(),
+            algorithm: "RS256".to_string(),
+            ..Default::default()
+        }];
+        assert!(validation_error(&config)
+            .contains("jwt.verification_keys.0.public_key_path is required"));
+    }
 }
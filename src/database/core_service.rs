@@ -1,24 +1,208 @@
 use super::mongodb::MongoClient;
-use crate::{handlers::error_handler::Errors, traits::model::ModelTrait};
+use crate::{
+    handlers::error_handler::{write_error_code, write_error_message, Errors},
+    models::{
+        bulk::{BulkOperation, BulkWriteMode, BulkWriteReport, WriteStatus},
+        pagination::{
+            normalize_page, normalize_page_size, page_offset, CursorPage, KeysetQuery, Paginated,
+        },
+    },
+    traits::model::ModelTrait,
+};
 use chrono::Utc;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
 use mongodb::{
-    bson::{doc, oid::ObjectId, Document},
+    bson::{self, doc, oid::ObjectId, Bson, Document},
+    error::ErrorKind,
     options::{
-        AggregateOptions, FindOneAndDeleteOptions, FindOneAndUpdateOptions, FindOneOptions,
-        FindOptions, InsertOneOptions, UpdateModifications,
+        AggregateOptions, CountOptions, FindOneAndDeleteOptions, FindOneAndUpdateOptions,
+        FindOneOptions, FindOptions, InsertManyOptions, InsertOneOptions, ReturnDocument,
+        UpdateModifications, UpdateOptions,
     },
-    results::InsertOneResult,
-    ClientSession,
+    results::{InsertOneResult, UpdateResult},
+    ClientSession, IndexModel,
 };
 use serde::{de::DeserializeOwned, Serialize};
 
+pub const DEFAULT_STREAM_BATCH_SIZE: u32 = 500;
+// Unordered bulk writes without a session keep at most this many operations in flight.
+const MAX_CONCURRENT_WRITES: usize = 16;
+
+// Maps an `insert_many` outcome to one status per id. Errors without per-document
+// write errors (network or write concern failures) are returned as they are, since
+// it is unknown which documents were written.
+fn insert_statuses(
+    ids: Vec<String>,
+    ordered: bool,
+    result: Result<(), mongodb::error::Error>,
+) -> Result<Vec<WriteStatus>, Errors> {
+    let mut statuses = ids
+        .into_iter()
+        .map(|id| WriteStatus::Inserted { id })
+        .collect::<Vec<_>>();
+    let Err(error) = result else {
+        return Ok(statuses);
+    };
+    let write_errors = match error.kind.as_ref() {
+        ErrorKind::BulkWrite(failure) if failure.write_concern_error.is_none() => {
+            failure.write_errors.clone()
+        }
+        _ => None,
+    };
+    let Some(write_errors) = write_errors else {
+        return Err(Errors::Database(error));
+    };
+    for write_error in &write_errors {
+        if let Some(status) = statuses.get_mut(write_error.index) {
+            *status = WriteStatus::Failed {
+                code: write_error_code(write_error.code).to_string(),
+                message: write_error_message(write_error.code, &write_error.message),
+            };
+        }
+    }
+    // An ordered insert stops at its first error.
+    let first_error = write_errors.iter().map(|error| error.index).min();
+    if let (true, Some(first_error)) = (ordered, first_error) {
+        for status in statuses.iter_mut().skip(first_error + 1) {
+            *status = WriteStatus::Skipped;
+        }
+    }
+    Ok(statuses)
+}
+
+fn exclude_deleted<Model: ModelTrait>(data_filter: Document) -> Document {
+    if Model::SOFT_DELETE {
+        doc! {"$and": [data_filter, {"is_deleted": {"$ne": true}}]}
+    } else {
+        data_filter
+    }
+}
+
+// Adds `fields` to the `operator` of an operator update, e.g. `$set`.
+fn merge_operator(update: &mut Document, operator: &str, fields: Document) -> Result<(), Errors> {
+    match update.get_mut(operator) {
+        Some(Bson::Document(existing)) => existing.extend(fields),
+        Some(_) => {
+            return Err(Errors::InternalError(format!(
+                "Invalid {} document",
+                operator
+            )))
+        }
+        None => {
+            update.insert(operator, fields);
+        }
+    }
+    Ok(())
+}
+
+fn stamp_updated_at(update: UpdateModifications) -> Result<UpdateModifications, Errors> {
+    let current_timestamp = Utc::now().timestamp() as u32;
+    match update {
+        UpdateModifications::Document(mut update) => {
+            merge_operator(&mut update, "$set", doc! {"updated_at": current_timestamp})?;
+            Ok(UpdateModifications::Document(update))
+        }
+        UpdateModifications::Pipeline(mut pipeline) => {
+            pipeline.push(doc! {"$set": {"updated_at": current_timestamp}});
+            Ok(UpdateModifications::Pipeline(pipeline))
+        }
+        _ => Err(Errors::InternalError("Pipeline error".to_string())),
+    }
+}
+
+// Whether any operator of `update` already writes `field` or a path inside it.
+fn writes_field(update: &Document, field: &str) -> bool {
+    update.values().any(|fields| match fields {
+        Bson::Document(fields) => fields.keys().any(|key| {
+            key == field
+                || key
+                    .strip_prefix(field)
+                    .is_some_and(|path| path.starts_with('.'))
+        }),
+        _ => false,
+    })
+}
+
+// Inserted documents get the same id and `created_at` that `create_one` would give them.
+// Upserts also match soft deleted documents and restore them, so an upsert never
+// inserts a second document next to a deleted one. Fields the filter pins or the
+// update already writes are left alone, the server rejects conflicting paths.
+fn stamp_upsert<Model: ModelTrait>(
+    data_filter: &Document,
+    update: UpdateModifications,
+) -> Result<UpdateModifications, Errors> {
+    let id = ObjectId::new().to_string();
+    let current_timestamp = Utc::now().timestamp();
+    match update {
+        UpdateModifications::Document(mut update) => {
+            let mut on_insert = Document::new();
+            if !data_filter.contains_key("_id") && !writes_field(&update, "_id") {
+                on_insert.insert("_id", id);
+            }
+            if !writes_field(&update, "created_at") {
+                on_insert.insert("created_at", current_timestamp);
+            }
+            let mut restore = Document::new();
+            if Model::SOFT_DELETE {
+                for (field, value) in [
+                    ("is_deleted", Bson::Boolean(false)),
+                    ("deleted_at", Bson::Null),
+                ] {
+                    if !writes_field(&update, field) {
+                        restore.insert(field, value);
+                    }
+                }
+            }
+            if !on_insert.is_empty() {
+                merge_operator(&mut update, "$setOnInsert", on_insert)?;
+            }
+            if !restore.is_empty() {
+                merge_operator(&mut update, "$set", restore)?;
+            }
+            Ok(UpdateModifications::Document(update))
+        }
+        UpdateModifications::Pipeline(mut pipeline) => {
+            let mut stage = doc! {
+                "_id": {"$ifNull": ["$_id", id]},
+                "created_at": {"$ifNull": ["$created_at", current_timestamp]},
+            };
+            if Model::SOFT_DELETE {
+                stage.insert("is_deleted", false);
+                stage.insert("deleted_at", Bson::Null);
+            }
+            pipeline.push(doc! {"$set": stage});
+            Ok(UpdateModifications::Pipeline(pipeline))
+        }
+        _ => Err(Errors::InternalError("Pipeline error".to_string())),
+    }
+}
+
+// Soft deleted documents are left out, except for upserts (see `stamp_upsert`).
+fn update_filter<Model: ModelTrait>(
+    data_filter: Document,
+    options: Option<&UpdateOptions>,
+) -> Document {
+    if options.and_then(|options| options.upsert) == Some(true) {
+        data_filter
+    } else {
+        exclude_deleted::<Model>(data_filter)
+    }
+}
+
+fn soft_delete_update() -> UpdateModifications {
+    let current_timestamp = Utc::now().timestamp();
+    UpdateModifications::Document(
+        doc! {"$set": {"is_deleted": true, "deleted_at": current_timestamp}},
+    )
+}
+
 impl MongoClient {
     pub async fn create_one<M: DeserializeOwned + Serialize + Clone + ModelTrait>(
         &self,
         collection_name: impl Into<String>,
         model: &mut M,
         options: Option<InsertOneOptions>,
-        session: Option<ClientSession>,
+        session: Option<&mut ClientSession>,
     ) -> Result<InsertOneResult, Errors> {
         let id = ObjectId::new().to_string();
         model.set_id(id);
@@ -31,96 +215,224 @@ impl MongoClient {
             .collection::<M>(collection_name.into().as_str());
         let result = match session {
             None => collection.insert_one(model, options).await,
-            Some(mut mongo_session) => {
+            Some(session) => {
                 collection
-                    .insert_one_with_session(model, options, &mut mongo_session)
+                    .insert_one_with_session(model, options, session)
                     .await
             }
         };
         match result {
             Ok(insert_result) => Ok(insert_result),
-            Err(error) => Err(Errors::InternalError(error.to_string())),
+            Err(error) => Err(Errors::Database(error)),
         }
     }
-    pub async fn read_one<Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin>(
+    pub async fn read_one<Model>(
         &self,
         collection_name: impl Into<String>,
         data_filter: Document,
         options: Option<FindOneOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Option<Model>, Errors>
+    where
+        Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin + ModelTrait,
+    {
+        self.read_one_with_deleted(
+            collection_name,
+            exclude_deleted::<Model>(data_filter),
+            options,
+            session,
+        )
+        .await
+    }
+    pub async fn read_one_with_deleted<
+        Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin,
+    >(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Document,
+        options: Option<FindOneOptions>,
+        session: Option<&mut ClientSession>,
     ) -> Result<Option<Model>, Errors> {
         let collection = self
             .client
             .database(&self.db_name)
             .collection::<Model>(collection_name.into().as_str());
-        let result = collection
-            .find_one(data_filter, options)
+        let result = match session {
+            Some(session) => {
+                collection
+                    .find_one_with_session(data_filter, options, session)
+                    .await
+            }
+            None => collection.find_one(data_filter, options).await,
+        };
+        result.map_err(Errors::Database)
+    }
+    pub async fn read_many<Model>(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Option<Document>,
+        options: Option<FindOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Vec<Model>, Errors>
+    where
+        Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin + ModelTrait,
+    {
+        let data_filter = exclude_deleted::<Model>(data_filter.unwrap_or_default());
+        self.read_many_with_deleted(collection_name, Some(data_filter), options, session)
             .await
-            .map_err(|error| Errors::InternalError(error.to_string()))?;
-        Ok(result)
     }
-    pub async fn read_many<Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin>(
+    pub async fn read_many_with_deleted<
+        Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin,
+    >(
         &self,
         collection_name: impl Into<String>,
         data_filter: Option<Document>,
         options: Option<FindOptions>,
+        session: Option<&mut ClientSession>,
     ) -> Result<Vec<Model>, Errors> {
         let collection = self
             .client
             .database(&self.db_name)
             .collection::<Model>(collection_name.into().as_str());
-        let mut cursor = collection
-            .find(data_filter, options)
-            .await
-            .map_err(|error| Errors::InternalError(error.to_string()))?;
         let mut result_vector = Vec::new();
-
-        while cursor
-            .advance()
-            .await
-            .map_err(|error| Errors::InternalError(error.to_string()))?
-        {
-            let doc = cursor
-                .deserialize_current()
-                .map_err(|error| Errors::InternalError(error.to_string()))?;
-
-            result_vector.push(doc);
+        match session {
+            Some(session) => {
+                let mut cursor = collection
+                    .find_with_session(data_filter, options, session)
+                    .await
+                    .map_err(Errors::Database)?;
+                while cursor.advance(session).await.map_err(Errors::Database)? {
+                    result_vector.push(cursor.deserialize_current().map_err(Errors::Database)?);
+                }
+            }
+            None => {
+                let mut cursor = collection
+                    .find(data_filter, options)
+                    .await
+                    .map_err(Errors::Database)?;
+                while cursor.advance().await.map_err(Errors::Database)? {
+                    result_vector.push(cursor.deserialize_current().map_err(Errors::Database)?);
+                }
+            }
         }
         Ok(result_vector)
     }
-    pub async fn update_one<Model: DeserializeOwned>(
+    // Yields documents as the cursor fetches them, `batch_size` at a time, so memory
+    // use does not grow with the result set. Defaults to `DEFAULT_STREAM_BATCH_SIZE`.
+    pub async fn stream_many<Model>(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Option<Document>,
+        options: Option<FindOptions>,
+        batch_size: Option<u32>,
+    ) -> Result<impl Stream<Item = Result<Model, Errors>> + 'static, Errors>
+    where
+        Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin + ModelTrait + 'static,
+    {
+        let data_filter = exclude_deleted::<Model>(data_filter.unwrap_or_default());
+        self.stream_many_with_deleted(collection_name, Some(data_filter), options, batch_size)
+            .await
+    }
+    pub async fn stream_many_with_deleted<
+        Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin + 'static,
+    >(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Option<Document>,
+        options: Option<FindOptions>,
+        batch_size: Option<u32>,
+    ) -> Result<impl Stream<Item = Result<Model, Errors>> + 'static, Errors> {
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<Model>(collection_name.into().as_str());
+        let mut options = options.unwrap_or_default();
+        options.batch_size = Some(batch_size.unwrap_or(DEFAULT_STREAM_BATCH_SIZE));
+        let cursor = collection
+            .find(data_filter, options)
+            .await
+            .map_err(Errors::Database)?;
+        Ok(cursor.map_err(Errors::Database))
+    }
+    pub async fn read_keyset<Model>(
+        &self,
+        collection_name: impl Into<String>,
+        query: KeysetQuery,
+        session: Option<&mut ClientSession>,
+    ) -> Result<CursorPage<Model>, Errors>
+    where
+        Model: DeserializeOwned + Serialize + Clone + Sync + Send + Unpin + ModelTrait,
+    {
+        let options = FindOptions::builder()
+            .sort(query.sort.clone())
+            .limit(query.limit as i64)
+            .build();
+        let rows = self
+            .read_many::<Model>(
+                collection_name,
+                Some(query.filter.clone()),
+                Some(options),
+                session,
+            )
+            .await?;
+        query.into_page(rows)
+    }
+    pub async fn count<Model: ModelTrait>(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Option<Document>,
+        options: Option<CountOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Errors> {
+        let data_filter = exclude_deleted::<Model>(data_filter.unwrap_or_default());
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<Model>(collection_name.into().as_str());
+        let result = match session {
+            Some(session) => {
+                collection
+                    .count_documents_with_session(data_filter, options, session)
+                    .await
+            }
+            None => collection.count_documents(data_filter, options).await,
+        };
+        result.map_err(Errors::Database)
+    }
+    pub async fn update_one<Model: DeserializeOwned + ModelTrait>(
+        &self,
+        collection_name: String,
+        data_filter: Document,
+        update: UpdateModifications,
+        options: Option<FindOneAndUpdateOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Option<Model>, Errors> {
+        self.update_one_with_deleted(
+            collection_name,
+            exclude_deleted::<Model>(data_filter),
+            update,
+            options,
+            session,
+        )
+        .await
+    }
+    pub async fn update_one_with_deleted<Model: DeserializeOwned>(
         &self,
         collection_name: String,
         data_filter: Document,
         update: UpdateModifications,
         options: Option<FindOneAndUpdateOptions>,
-        session: Option<ClientSession>,
+        session: Option<&mut ClientSession>,
     ) -> Result<Option<Model>, Errors> {
         let collection = self
             .client
             .database(&self.db_name)
             .collection::<Model>(collection_name.as_str());
-        let current_timestamp = Utc::now().timestamp() as u32;
-        let update_doc = doc! {"$set": {"updated_at": current_timestamp}};
-        let new_update = match update {
-            UpdateModifications::Document(doc) => {
-                UpdateModifications::Pipeline(vec![doc, update_doc])
-            }
-            UpdateModifications::Pipeline(pipeline) => {
-                let mut pipeline_vec = pipeline;
-                pipeline_vec.push(update_doc);
-                UpdateModifications::Pipeline(pipeline_vec)
-            }
-            _ => return Err(Errors::InternalError("Pipeline error".to_string())),
-        };
+        let new_update = stamp_updated_at(update)?;
         let result = match session {
-            Some(mut session) => {
+            Some(session) => {
                 collection
-                    .find_one_and_update_with_session(
-                        data_filter,
-                        new_update,
-                        options,
-                        &mut session,
-                    )
+                    .find_one_and_update_with_session(data_filter, new_update, options, session)
                     .await
             }
             None => {
@@ -131,7 +443,7 @@ impl MongoClient {
         };
         match result {
             Ok(update_result) => Ok(update_result),
-            Err(error) => Err(Errors::InternalError(error.to_string())),
+            Err(error) => Err(Errors::Database(error)),
         }
     }
 
@@ -140,7 +452,62 @@ impl MongoClient {
         collection_name: String,
         data_filter: Document,
         options: Option<FindOneAndDeleteOptions>,
-        session: Option<ClientSession>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Option<Model>, Errors>
+    where
+        Model: DeserializeOwned + Serialize + Clone + ModelTrait,
+    {
+        if !Model::SOFT_DELETE {
+            return self
+                .purge_one(collection_name, data_filter, options, session)
+                .await;
+        }
+        let update_options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        self.update_one::<Model>(
+            collection_name,
+            data_filter,
+            soft_delete_update(),
+            Some(update_options),
+            session,
+        )
+        .await
+    }
+    pub async fn restore_one<Model>(
+        &self,
+        collection_name: String,
+        data_filter: Document,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Option<Model>, Errors>
+    where
+        Model: DeserializeOwned + Serialize + Clone + ModelTrait,
+    {
+        if !Model::SOFT_DELETE {
+            return Err(Errors::InternalError(
+                "Model does not support soft delete".to_string(),
+            ));
+        }
+        let update_options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        self.update_one_with_deleted::<Model>(
+            collection_name,
+            doc! {"$and": [data_filter, {"is_deleted": true}]},
+            UpdateModifications::Document(
+                doc! {"$set": {"is_deleted": false, "deleted_at": Bson::Null}},
+            ),
+            Some(update_options),
+            session,
+        )
+        .await
+    }
+    pub async fn purge_one<Model>(
+        &self,
+        collection_name: String,
+        data_filter: Document,
+        options: Option<FindOneAndDeleteOptions>,
+        session: Option<&mut ClientSession>,
     ) -> Result<Option<Model>, Errors>
     where
         Model: DeserializeOwned + Serialize + Clone + ModelTrait,
@@ -150,9 +517,9 @@ impl MongoClient {
             .database(&self.db_name)
             .collection::<Model>(collection_name.as_str());
         let result = match session {
-            Some(mut session) => {
+            Some(session) => {
                 collection
-                    .find_one_and_delete_with_session(data_filter, options, &mut session)
+                    .find_one_and_delete_with_session(data_filter, options, session)
                     .await
             }
             None => collection.find_one_and_delete(data_filter, options).await,
@@ -160,39 +527,52 @@ impl MongoClient {
 
         match result {
             Ok(delete_result) => Ok(delete_result),
-            Err(error) => Err(Errors::InternalError(error.to_string())),
+            Err(error) => Err(Errors::Database(error)),
         }
     }
-    pub async fn query_read<Model>(
+    pub async fn create_indexes<Model>(
+        &self,
+        collection_name: impl Into<String>,
+        indexes: Vec<IndexModel>,
+    ) -> Result<Vec<String>, Errors> {
+        if indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<Model>(collection_name.into().as_str());
+        collection
+            .create_indexes(indexes, None)
+            .await
+            .map(|result| result.index_names)
+            .map_err(Errors::Database)
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub async fn query_read<Model: ModelTrait>(
         &self,
         collection_name: String,
         aggregate: Vec<Document>,
-        page: Option<u8>,
-        page_size: Option<u8>,
+        page: Option<u64>,
+        page_size: Option<u64>,
         paging_data: bool,
         options: Option<AggregateOptions>,
+        session: Option<&mut ClientSession>,
     ) -> Result<Document, Errors> {
         let collection = self
             .client
             .database(&self.db_name)
             .collection::<Model>(collection_name.as_str());
-        let mut aggregate_pipeline = aggregate.clone();
+        let mut aggregate_pipeline = Vec::new();
+        if Model::SOFT_DELETE {
+            aggregate_pipeline.push(doc! {"$match": exclude_deleted::<Model>(Document::new())});
+        }
+        aggregate_pipeline.extend(aggregate);
         if paging_data {
-            let page_size: i32 = match page_size {
-                Some(val) => {
-                    if val >= 100 {
-                        10
-                    } else {
-                        val.into()
-                    }
-                }
-                None => 10,
-            };
-            let page: i32 = match page {
-                Some(val) => val.into(),
-                None => 1,
-            };
-            let skip: i32 = (page - 1) * page_size;
+            let page = normalize_page(page);
+            let page_size = normalize_page_size(page_size);
+            let skip = page_offset(page, page_size)? as i64;
+            let (page, page_size) = (page as i64, page_size as i64);
 
             let mut additional_aggregate = vec![
                 doc! {"$facet": {"data": [{"$skip": skip}, {"$limit": page_size + 1}], "total_count": [{"$count": "total"}]}},
@@ -210,20 +590,26 @@ impl MongoClient {
             ];
             aggregate_pipeline.append(&mut additional_aggregate);
         }
-        let mut cursor = collection
-            .aggregate(aggregate_pipeline, options)
-            .await
-            .map_err(|error| Errors::InternalError(error.to_string()))?;
         let mut result_document = Document::new();
-        while cursor
-            .advance()
-            .await
-            .map_err(|error| Errors::InternalError(error.to_string()))?
-        {
-            let doc = cursor
-                .deserialize_current()
-                .map_err(|error| Errors::InternalError(error.to_string()))?;
-            result_document.extend(doc);
+        match session {
+            Some(session) => {
+                let mut cursor = collection
+                    .aggregate_with_session(aggregate_pipeline, options, session)
+                    .await
+                    .map_err(Errors::Database)?;
+                while cursor.advance(session).await.map_err(Errors::Database)? {
+                    result_document.extend(cursor.deserialize_current().map_err(Errors::Database)?);
+                }
+            }
+            None => {
+                let mut cursor = collection
+                    .aggregate(aggregate_pipeline, options)
+                    .await
+                    .map_err(Errors::Database)?;
+                while cursor.advance().await.map_err(Errors::Database)? {
+                    result_document.extend(cursor.deserialize_current().map_err(Errors::Database)?);
+                }
+            }
         }
         /*
                 while let Some(doc) = cursor.next().await {
@@ -235,4 +621,534 @@ impl MongoClient {
         */
         Ok(result_document)
     }
+    pub async fn query_read_paginated<Model>(
+        &self,
+        collection_name: String,
+        aggregate: Vec<Document>,
+        page: Option<u64>,
+        page_size: Option<u64>,
+        options: Option<AggregateOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Paginated<Model>, Errors>
+    where
+        Model: DeserializeOwned + ModelTrait,
+    {
+        let result_document = self
+            .query_read::<Model>(
+                collection_name,
+                aggregate,
+                page,
+                page_size,
+                true,
+                options,
+                session,
+            )
+            .await?;
+        bson::from_document(result_document).map_err(|error| Errors::Database(error.into()))
+    }
+    // One status per model, at the same index. Documents the server rejects are
+    // reported as failed instead of failing the whole call.
+    pub async fn create_many<M: DeserializeOwned + Serialize + Clone + ModelTrait>(
+        &self,
+        collection_name: impl Into<String>,
+        models: &mut [M],
+        options: Option<InsertManyOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Vec<WriteStatus>, Errors> {
+        if models.is_empty() {
+            return Ok(Vec::new());
+        }
+        let current_timestamp = Utc::now().timestamp() as u64;
+        for model in models.iter_mut() {
+            model.set_id(ObjectId::new().to_string());
+            model.set_created_at(current_timestamp);
+            model.set_updated_at(current_timestamp);
+        }
+        // The server inserts in order unless told otherwise.
+        let ordered = options
+            .as_ref()
+            .and_then(|options| options.ordered)
+            .unwrap_or(true);
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<M>(collection_name.into().as_str());
+        let result = match session {
+            None => collection.insert_many(models.iter(), options).await,
+            Some(session) => {
+                collection
+                    .insert_many_with_session(models.iter(), options, session)
+                    .await
+            }
+        };
+        let ids = models
+            .iter()
+            .map(|model| model.get_id().to_string())
+            .collect();
+        insert_statuses(ids, ordered, result.map(|_| ()))
+    }
+    async fn update_documents<Model: ModelTrait>(
+        &self,
+        collection_name: &str,
+        data_filter: Document,
+        update: UpdateModifications,
+        many: bool,
+        options: Option<UpdateOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<UpdateResult, Errors> {
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<Model>(collection_name);
+        let data_filter = update_filter::<Model>(data_filter, options.as_ref());
+        let result = match (session, many) {
+            (None, false) => collection.update_one(data_filter, update, options).await,
+            (None, true) => collection.update_many(data_filter, update, options).await,
+            (Some(session), false) => {
+                collection
+                    .update_one_with_session(data_filter, update, options, session)
+                    .await
+            }
+            (Some(session), true) => {
+                collection
+                    .update_many_with_session(data_filter, update, options, session)
+                    .await
+            }
+        };
+        result.map_err(Errors::Database)
+    }
+    pub async fn update_many<Model: ModelTrait>(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Document,
+        update: UpdateModifications,
+        options: Option<UpdateOptions>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<UpdateResult, Errors> {
+        self.update_documents::<Model>(
+            &collection_name.into(),
+            data_filter,
+            stamp_updated_at(update)?,
+            true,
+            options,
+            session,
+        )
+        .await
+    }
+    pub async fn upsert_one<Model: DeserializeOwned + ModelTrait>(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Document,
+        update: UpdateModifications,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Model, Errors> {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let update = stamp_upsert::<Model>(&data_filter, update)?;
+        self.update_one_with_deleted::<Model>(
+            collection_name.into(),
+            data_filter,
+            update,
+            Some(options),
+            session,
+        )
+        .await?
+        .ok_or_else(|| Errors::InternalError("Upsert returned no document".to_string()))
+    }
+    async fn delete_documents<Model: ModelTrait>(
+        &self,
+        collection_name: &str,
+        data_filter: Document,
+        many: bool,
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Errors> {
+        if Model::SOFT_DELETE {
+            return self
+                .update_documents::<Model>(
+                    collection_name,
+                    data_filter,
+                    stamp_updated_at(soft_delete_update())?,
+                    many,
+                    None,
+                    session,
+                )
+                .await
+                .map(|result| result.modified_count);
+        }
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<Model>(collection_name);
+        let result = match (session, many) {
+            (None, false) => collection.delete_one(data_filter, None).await,
+            (None, true) => collection.delete_many(data_filter, None).await,
+            (Some(session), false) => {
+                collection
+                    .delete_one_with_session(data_filter, None, session)
+                    .await
+            }
+            (Some(session), true) => {
+                collection
+                    .delete_many_with_session(data_filter, None, session)
+                    .await
+            }
+        };
+        result
+            .map(|result| result.deleted_count)
+            .map_err(Errors::Database)
+    }
+    // Soft deletes when the model supports it. Returns how many documents were deleted.
+    pub async fn delete_many<Model: ModelTrait>(
+        &self,
+        collection_name: impl Into<String>,
+        data_filter: Document,
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Errors> {
+        self.delete_documents::<Model>(&collection_name.into(), data_filter, true, session)
+            .await
+    }
+    async fn write_one<Model>(
+        &self,
+        collection_name: &str,
+        operation: BulkOperation<Model>,
+        session: Option<&mut ClientSession>,
+    ) -> Result<WriteStatus, Errors>
+    where
+        Model: DeserializeOwned + Serialize + Clone + ModelTrait,
+    {
+        match operation {
+            BulkOperation::InsertOne(mut model) => {
+                self.create_one(collection_name, &mut model, None, session)
+                    .await?;
+                Ok(WriteStatus::Inserted {
+                    id: model.get_id().to_string(),
+                })
+            }
+            BulkOperation::UpdateOne { filter, update } => self
+                .update_documents::<Model>(
+                    collection_name,
+                    filter,
+                    stamp_updated_at(update)?,
+                    false,
+                    None,
+                    session,
+                )
+                .await
+                .map(WriteStatus::from),
+            BulkOperation::UpdateMany { filter, update } => self
+                .update_documents::<Model>(
+                    collection_name,
+                    filter,
+                    stamp_updated_at(update)?,
+                    true,
+                    None,
+                    session,
+                )
+                .await
+                .map(WriteStatus::from),
+            BulkOperation::UpsertOne { filter, update } => {
+                let update = stamp_upsert::<Model>(&filter, stamp_updated_at(update)?)?;
+                self.update_documents::<Model>(
+                    collection_name,
+                    filter,
+                    update,
+                    false,
+                    Some(UpdateOptions::builder().upsert(true).build()),
+                    session,
+                )
+                .await
+                .map(WriteStatus::from)
+            }
+            BulkOperation::DeleteOne { filter } => self
+                .delete_documents::<Model>(collection_name, filter, false, session)
+                .await
+                .map(|deleted_count| WriteStatus::Deleted { deleted_count }),
+            BulkOperation::DeleteMany { filter } => self
+                .delete_documents::<Model>(collection_name, filter, true, session)
+                .await
+                .map(|deleted_count| WriteStatus::Deleted { deleted_count }),
+        }
+    }
+    // Consecutive inserts go to the server as one `insert_many`. Returns whether any
+    // of them failed.
+    async fn insert_batch<Model>(
+        &self,
+        collection_name: &str,
+        batch: Vec<(usize, Model)>,
+        ordered: bool,
+        session: Option<&mut ClientSession>,
+        results: &mut [WriteStatus],
+    ) -> bool
+    where
+        Model: DeserializeOwned + Serialize + Clone + ModelTrait,
+    {
+        let (indexes, mut models): (Vec<usize>, Vec<Model>) = batch.into_iter().unzip();
+        let options = InsertManyOptions::builder().ordered(ordered).build();
+        let statuses = self
+            .create_many(collection_name, &mut models, Some(options), session)
+            .await
+            .unwrap_or_else(|error| vec![WriteStatus::failed(&error); indexes.len()]);
+        let failed = !statuses.iter().all(WriteStatus::is_success);
+        for (index, status) in indexes.into_iter().zip(statuses) {
+            results[index] = status;
+        }
+        failed
+    }
+    // Runs mixed writes on one collection. Failures are reported per operation in the
+    // returned report instead of failing the whole call.
+    pub async fn bulk_write<Model>(
+        &self,
+        collection_name: impl Into<String>,
+        operations: Vec<BulkOperation<Model>>,
+        mode: BulkWriteMode,
+        mut session: Option<&mut ClientSession>,
+    ) -> BulkWriteReport
+    where
+        Model: DeserializeOwned + Serialize + Clone + ModelTrait,
+    {
+        let collection_name = collection_name.into();
+        let mut results = vec![WriteStatus::Skipped; operations.len()];
+        match mode {
+            BulkWriteMode::Ordered => {
+                let mut operations = operations.into_iter().enumerate().peekable();
+                while let Some((index, operation)) = operations.next() {
+                    let failed = match operation {
+                        BulkOperation::InsertOne(model) => {
+                            let mut batch = vec![(index, model)];
+                            while let Some((index, BulkOperation::InsertOne(model))) = operations
+                                .next_if(|(_, operation)| {
+                                    matches!(operation, BulkOperation::InsertOne(_))
+                                })
+                            {
+                                batch.push((index, model));
+                            }
+                            self.insert_batch(
+                                &collection_name,
+                                batch,
+                                true,
+                                session.as_deref_mut(),
+                                &mut results,
+                            )
+                            .await
+                        }
+                        operation => {
+                            let status = self
+                                .write_one(&collection_name, operation, session.as_deref_mut())
+                                .await
+                                .unwrap_or_else(|error| WriteStatus::failed(&error));
+                            let failed = !status.is_success();
+                            results[index] = status;
+                            failed
+                        }
+                    };
+                    if failed {
+                        break;
+                    }
+                }
+            }
+            BulkWriteMode::Unordered => {
+                let (inserts, operations): (Vec<_>, Vec<_>) = operations
+                    .into_iter()
+                    .enumerate()
+                    .partition(|(_, operation)| matches!(operation, BulkOperation::InsertOne(_)));
+                let inserts = inserts
+                    .into_iter()
+                    .filter_map(|(index, operation)| match operation {
+                        BulkOperation::InsertOne(model) => Some((index, model)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                if !inserts.is_empty() {
+                    self.insert_batch(
+                        &collection_name,
+                        inserts,
+                        false,
+                        session.as_deref_mut(),
+                        &mut results,
+                    )
+                    .await;
+                }
+                // A session cannot be shared between concurrent operations.
+                let statuses = match session {
+                    Some(session) => {
+                        let mut statuses = Vec::new();
+                        for (index, operation) in operations {
+                            let status = self
+                                .write_one(&collection_name, operation, Some(&mut *session))
+                                .await;
+                            statuses.push((index, status));
+                        }
+                        statuses
+                    }
+                    None => {
+                        stream::iter(operations)
+                            .map(|(index, operation)| {
+                                let collection_name = &collection_name;
+                                async move {
+                                    let status =
+                                        self.write_one(collection_name, operation, None).await;
+                                    (index, status)
+                                }
+                            })
+                            .buffer_unordered(MAX_CONCURRENT_WRITES)
+                            .collect::<Vec<_>>()
+                            .await
+                    }
+                };
+                for (index, status) in statuses {
+                    results[index] = status.unwrap_or_else(|error| WriteStatus::failed(&error));
+                }
+            }
+        }
+        BulkWriteReport { mode, results }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{refresh_token::RefreshTokenModel, user::UserModel};
+    use mongodb::error::BulkWriteFailure;
+
+    fn ids() -> Vec<String> {
+        vec!["a".to_string(), "b".to_string(), "c".to_string()]
+    }
+
+    fn bulk_write_error(index: i32) -> mongodb::error::Error {
+        let failure: BulkWriteFailure = bson::from_document(doc! {
+            "writeErrors": [{
+                "index": index,
+                "code": 11000,
+                "errmsg": "E11000 duplicate key error collection: db.users index: email_1 dup key: { email: \"ada@example.com\" }",
+            }],
+        })
+        .unwrap();
+        ErrorKind::BulkWrite(failure).into()
+    }
+
+    #[test]
+    fn reports_every_inserted_id() {
+        let statuses = insert_statuses(ids(), true, Ok(())).unwrap();
+        assert_eq!(
+            statuses,
+            ids()
+                .into_iter()
+                .map(|id| WriteStatus::Inserted { id })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn skips_documents_after_an_ordered_failure() {
+        let statuses = insert_statuses(ids(), true, Err(bulk_write_error(1))).unwrap();
+        assert_eq!(
+            statuses[0],
+            WriteStatus::Inserted {
+                id: "a".to_string()
+            }
+        );
+        assert_eq!(
+            statuses[1],
+            WriteStatus::Failed {
+                code: "duplicate_key".to_string(),
+                message: Some("A record with this email already exists".to_string()),
+            }
+        );
+        assert_eq!(statuses[2], WriteStatus::Skipped);
+    }
+
+    #[test]
+    fn keeps_documents_after_an_unordered_failure() {
+        let statuses = insert_statuses(ids(), false, Err(bulk_write_error(0))).unwrap();
+        assert!(matches!(statuses[0], WriteStatus::Failed { .. }));
+        assert_eq!(
+            statuses[2],
+            WriteStatus::Inserted {
+                id: "c".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn returns_errors_without_write_errors() {
+        let error = std::io::Error::from(std::io::ErrorKind::ConnectionReset).into();
+        assert!(insert_statuses(ids(), true, Err(error)).is_err());
+    }
+
+    fn stamped<Model: ModelTrait>(data_filter: Document, update: Document) -> Document {
+        match stamp_upsert::<Model>(&data_filter, UpdateModifications::Document(update)).unwrap() {
+            UpdateModifications::Document(update) => update,
+            _ => panic!("expected a document update"),
+        }
+    }
+
+    #[test]
+    fn upserts_get_an_id_and_created_at_on_insert() {
+        let update =
+            stamped::<RefreshTokenModel>(doc! {"family_id": "f"}, doc! {"$set": {"revoked": true}});
+        let on_insert = update.get_document("$setOnInsert").unwrap();
+        assert!(on_insert.get_str("_id").is_ok());
+        assert!(on_insert.get_i64("created_at").is_ok());
+        assert_eq!(
+            update.get_document("$set").unwrap(),
+            &doc! {"revoked": true}
+        );
+    }
+
+    #[test]
+    fn upserts_keep_an_id_pinned_by_the_filter() {
+        let update =
+            stamped::<RefreshTokenModel>(doc! {"_id": "pinned"}, doc! {"$set": {"revoked": true}});
+        let on_insert = update.get_document("$setOnInsert").unwrap();
+        assert!(!on_insert.contains_key("_id"));
+        assert!(on_insert.contains_key("created_at"));
+    }
+
+    #[test]
+    fn upserts_keep_fields_the_update_writes() {
+        let update = stamped::<RefreshTokenModel>(
+            doc! {"family_id": "f"},
+            doc! {"$set": {"created_at": 1}, "$setOnInsert": {"_id": "mine"}},
+        );
+        assert_eq!(
+            update.get_document("$setOnInsert").unwrap(),
+            &doc! {"_id": "mine"}
+        );
+        assert_eq!(
+            update.get_document("$set").unwrap(),
+            &doc! {"created_at": 1}
+        );
+    }
+
+    #[test]
+    fn upserts_restore_soft_deleted_matches() {
+        let update = stamped::<UserModel>(
+            doc! {"email": "ada@example.com"},
+            doc! {"$set": {"first_name": "Ada"}},
+        );
+        assert_eq!(
+            update.get_document("$set").unwrap(),
+            &doc! {"first_name": "Ada", "is_deleted": false, "deleted_at": Bson::Null}
+        );
+        let filter = doc! {"email": "ada@example.com"};
+        let upsert = UpdateOptions::builder().upsert(true).build();
+        assert_eq!(
+            update_filter::<UserModel>(filter.clone(), Some(&upsert)),
+            filter
+        );
+        assert_eq!(
+            update_filter::<UserModel>(filter.clone(), None),
+            exclude_deleted::<UserModel>(filter)
+        );
+    }
+
+    #[test]
+    fn upserts_leave_models_without_soft_delete_alone() {
+        let update =
+            stamped::<RefreshTokenModel>(doc! {"family_id": "f"}, doc! {"$inc": {"uses": 1}});
+        assert!(!update.contains_key("$set"));
+        assert_eq!(update.get_document("$inc").unwrap(), &doc! {"uses": 1});
+    }
 }
@@ -0,0 +1,31 @@
+use crate::{database::mongodb::MongoClient, traits::health::HealthCheck};
+use async_trait::async_trait;
+use mongodb::bson::doc;
+use serde_json::{json, Value};
+use std::time::Instant;
+
+#[async_trait]
+impl HealthCheck for MongoClient {
+    fn name(&self) -> &'static str {
+        "mongodb"
+    }
+
+    async fn check(&self) -> Result<Value, String> {
+        let database = self.client.database(&self.db_name);
+        let started_at = Instant::now();
+        database
+            .run_command(doc! {"ping": 1}, None)
+            .await
+            .map_err(|error| error.to_string())?;
+        let ping_latency_ms = started_at.elapsed().as_secs_f64() * 1000.0;
+        let build_info = database
+            .run_command(doc! {"buildInfo": 1}, None)
+            .await
+            .map_err(|error| error.to_string())?;
+        Ok(json!({
+            "ping_latency_ms": ping_latency_ms,
+            "server_version": build_info.get_str("version").unwrap_or_default(),
+            "pool": self.pool_stats.snapshot(),
+        }))
+    }
+}
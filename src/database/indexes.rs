@@ -0,0 +1,427 @@
+use super::mongodb::MongoClient;
+use crate::{
+    config::{IndexSyncMode, IndexesConfig},
+    handlers::error_handler::Errors,
+    models::{
+        oauth::OAuthStateModel, refresh_token::RefreshTokenModel, revoked_token::RevokedTokenModel,
+        user::UserModel,
+    },
+    traits::model::ModelTrait,
+};
+use futures::TryStreamExt;
+use log::{info, warn};
+use mongodb::{
+    bson::{Bson, Document},
+    error::ErrorKind,
+    options::IndexOptions,
+    IndexModel,
+};
+use std::{collections::BTreeMap, fmt};
+
+const ID_INDEX: &str = "_id_";
+const NAMESPACE_NOT_FOUND: i32 = 26;
+
+// The server's naming scheme, `{"email": 1, "created_at": -1}` is `email_1_created_at_-1`
+// and `{"bio": "text"}` is `bio_text`.
+pub fn index_name(index: &IndexModel) -> String {
+    if let Some(name) = index
+        .options
+        .as_ref()
+        .and_then(|options| options.name.clone())
+    {
+        return name;
+    }
+    index
+        .keys
+        .iter()
+        .map(|(key, value)| format!("{}_{}", key, key_value(value)))
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn named(mut index: IndexModel) -> IndexModel {
+    let name = index_name(&index);
+    index.options.get_or_insert_with(IndexOptions::default).name = Some(name);
+    index
+}
+
+// Numbers come back from the server as whichever bson type it stored them with.
+fn key_value(value: &Bson) -> String {
+    match value {
+        Bson::String(kind) => kind.clone(),
+        Bson::Int32(number) => number.to_string(),
+        Bson::Int64(number) => number.to_string(),
+        Bson::Double(number) => number.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn is_text(value: &Bson) -> bool {
+    value.as_str() == Some("text")
+}
+
+// Text fields are stored as `{"_fts": "text", "_ftsx": 1}` with the fields in `weights`.
+fn stored_keys(keys: &Document) -> Vec<(String, String)> {
+    let mut stored = Vec::new();
+    for (key, value) in keys {
+        if !is_text(value) && key != "_ftsx" {
+            stored.push((key.clone(), key_value(value)));
+        } else if !stored.iter().any(|(key, _)| key == "_fts") {
+            stored.push(("_fts".to_string(), "text".to_string()));
+            stored.push(("_ftsx".to_string(), "1".to_string()));
+        }
+    }
+    stored
+}
+
+fn text_weights(index: &IndexModel, declared: bool) -> BTreeMap<String, String> {
+    let weights = index
+        .options
+        .as_ref()
+        .and_then(|options| options.weights.clone())
+        .unwrap_or_default();
+    let mut text_weights = BTreeMap::new();
+    if declared {
+        for (key, _) in index.keys.iter().filter(|(_, value)| is_text(value)) {
+            text_weights.insert(key.clone(), "1".to_string());
+        }
+    }
+    for (key, weight) in &weights {
+        text_weights.insert(key.clone(), key_value(weight));
+    }
+    text_weights
+}
+
+// Describes how a stored index differs from its declaration, empty when it matches.
+fn differences(declared: &IndexModel, existing: &IndexModel) -> Vec<String> {
+    let mut differences = Vec::new();
+    let default_options = IndexOptions::default();
+    let options = declared.options.as_ref().unwrap_or(&default_options);
+    let existing_options = existing.options.as_ref().unwrap_or(&default_options);
+    let mut compare = |what: &str, declared: String, existing: String| {
+        if declared != existing {
+            differences.push(format!("{}: {} -> {}", what, existing, declared));
+        }
+    };
+    compare(
+        "keys",
+        format!("{:?}", stored_keys(&declared.keys)),
+        format!("{:?}", stored_keys(&existing.keys)),
+    );
+    compare(
+        "unique",
+        options.unique.unwrap_or(false).to_string(),
+        existing_options.unique.unwrap_or(false).to_string(),
+    );
+    compare(
+        "sparse",
+        options.sparse.unwrap_or(false).to_string(),
+        existing_options.sparse.unwrap_or(false).to_string(),
+    );
+    compare(
+        "expire_after",
+        format!("{:?}", options.expire_after),
+        format!("{:?}", existing_options.expire_after),
+    );
+    compare(
+        "partial_filter_expression",
+        format!("{:?}", options.partial_filter_expression),
+        format!("{:?}", existing_options.partial_filter_expression),
+    );
+    if declared.keys.values().any(is_text) {
+        compare(
+            "weights",
+            format!("{:?}", text_weights(declared, true)),
+            format!("{:?}", text_weights(existing, false)),
+        );
+    }
+    differences
+}
+
+#[derive(Debug, Default)]
+pub struct IndexPlan {
+    pub collection: &'static str,
+    pub mode: IndexSyncMode,
+    pub unchanged: Vec<String>,
+    pub missing: Vec<IndexModel>,
+    // Declared indexes stored with other keys or options, with what differs.
+    pub changed: Vec<(IndexModel, Vec<String>)>,
+    // Stored indexes no model declares.
+    pub undeclared: Vec<String>,
+}
+
+impl IndexPlan {
+    pub fn is_in_sync(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty() && self.undeclared.is_empty()
+    }
+}
+
+impl fmt::Display for IndexPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.collection)?;
+        if self.mode == IndexSyncMode::DryRun {
+            write!(f, " (dry run)")?;
+        }
+        write!(f, ": {} in sync", self.unchanged.len())?;
+        for index in &self.missing {
+            write!(f, ", missing {}", index_name(index))?;
+        }
+        for (index, differences) in &self.changed {
+            write!(
+                f,
+                ", changed {} ({})",
+                index_name(index),
+                differences.join(", ")
+            )?;
+        }
+        for name in &self.undeclared {
+            write!(f, ", undeclared {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+// Compares `declared` indexes with `existing` ones, matching them by name.
+fn plan(
+    collection: &'static str,
+    declared: Vec<IndexModel>,
+    mut existing: Vec<IndexModel>,
+) -> IndexPlan {
+    let mut plan = IndexPlan {
+        collection,
+        ..Default::default()
+    };
+    for declared in declared.into_iter().map(named) {
+        let name = index_name(&declared);
+        match existing.iter().position(|index| index_name(index) == name) {
+            Some(position) => {
+                let differences = differences(&declared, &existing.remove(position));
+                if differences.is_empty() {
+                    plan.unchanged.push(name);
+                } else {
+                    plan.changed.push((declared, differences));
+                }
+            }
+            None => plan.missing.push(declared),
+        }
+    }
+    plan.undeclared = existing
+        .iter()
+        .map(index_name)
+        .filter(|name| name != ID_INDEX)
+        .collect();
+    plan
+}
+
+impl MongoClient {
+    async fn existing_indexes(&self, collection_name: &str) -> Result<Vec<IndexModel>, Errors> {
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<Document>(collection_name);
+        match collection.list_indexes(None).await {
+            Ok(cursor) => cursor.try_collect().await.map_err(Errors::Database),
+            Err(error) => match error.kind.as_ref() {
+                ErrorKind::Command(command) if command.code == NAMESPACE_NOT_FOUND => {
+                    Ok(Vec::new())
+                }
+                _ => Err(Errors::Database(error)),
+            },
+        }
+    }
+
+    // Compares the indexes `Model` declares with the ones stored.
+    pub async fn plan_indexes<Model: ModelTrait>(&self) -> Result<IndexPlan, Errors> {
+        let existing = self.existing_indexes(Model::COLLECTION_NAME).await?;
+        Ok(plan(Model::COLLECTION_NAME, Model::indexes(), existing))
+    }
+
+    // Creates missing indexes and, when `drop` is set, recreates changed ones and drops
+    // undeclared ones. Anything left out is logged as drift.
+    pub async fn sync_indexes<Model: ModelTrait>(
+        &self,
+        config: &IndexesConfig,
+    ) -> Result<IndexPlan, Errors> {
+        let mut plan = self.plan_indexes::<Model>().await?;
+        plan.mode = config.sync;
+        let collection_name = Model::COLLECTION_NAME;
+        let apply = config.sync == IndexSyncMode::Apply;
+        let drop = apply && config.drop;
+        let collection = self
+            .client
+            .database(&self.db_name)
+            .collection::<Document>(collection_name);
+        let mut create = Vec::new();
+        for index in &plan.missing {
+            if apply {
+                info!("Creating index {}.{}", collection_name, index_name(index));
+            } else {
+                info!(
+                    "Would create index {}.{}",
+                    collection_name,
+                    index_name(index)
+                );
+            }
+            create.push(index.clone());
+        }
+        for (index, differences) in &plan.changed {
+            let name = index_name(index);
+            let differences = differences.join(", ");
+            if drop {
+                info!(
+                    "Recreating index {}.{} ({})",
+                    collection_name, name, differences
+                );
+                collection
+                    .drop_index(&name, None)
+                    .await
+                    .map_err(Errors::Database)?;
+                create.push(index.clone());
+            } else {
+                warn!(
+                    "Index {}.{} differs from its declaration ({})",
+                    collection_name, name, differences
+                );
+            }
+        }
+        for name in &plan.undeclared {
+            if drop {
+                info!("Dropping undeclared index {}.{}", collection_name, name);
+                collection
+                    .drop_index(name, None)
+                    .await
+                    .map_err(Errors::Database)?;
+            } else {
+                warn!(
+                    "Index {}.{} is not declared by any model",
+                    collection_name, name
+                );
+            }
+        }
+        if apply {
+            self.create_indexes::<Document>(collection_name, create)
+                .await?;
+        }
+        Ok(plan)
+    }
+}
+
+// Every model with a collection. New models have to be added here for their
+// indexes to be managed.
+pub async fn sync_all(
+    client: &MongoClient,
+    config: &IndexesConfig,
+) -> Result<Vec<IndexPlan>, Errors> {
+    if config.sync == IndexSyncMode::Off {
+        return Ok(Vec::new());
+    }
+    Ok(vec![
+        client.sync_indexes::<UserModel>(config).await?,
+        client.sync_indexes::<RefreshTokenModel>(config).await?,
+        client.sync_indexes::<RevokedTokenModel>(config).await?,
+        client.sync_indexes::<OAuthStateModel>(config).await?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+    use std::time::Duration;
+
+    fn index(keys: Document) -> IndexModel {
+        IndexModel::builder().keys(keys).build()
+    }
+
+    fn index_with(keys: Document, options: IndexOptions) -> IndexModel {
+        IndexModel::builder().keys(keys).options(options).build()
+    }
+
+    fn unique() -> IndexOptions {
+        IndexOptions::builder().unique(true).build()
+    }
+
+    #[test]
+    fn names_indexes_like_the_server() {
+        assert_eq!(
+            index_name(&index(doc! {"email": 1, "created_at": -1})),
+            "email_1_created_at_-1"
+        );
+        assert_eq!(index_name(&index(doc! {"bio": "text"})), "bio_text");
+        assert_eq!(index_name(&index(doc! {"age": 1.0})), "age_1");
+        let named = index_with(
+            doc! {"email": 1},
+            IndexOptions::builder().name("by_email".to_string()).build(),
+        );
+        assert_eq!(index_name(&named), "by_email");
+    }
+
+    #[test]
+    fn reads_text_keys_the_way_they_are_stored() {
+        let declared = doc! {"tenant": 1, "bio": "text", "name": "text"};
+        let stored = doc! {"tenant": 1_i64, "_fts": "text", "_ftsx": 1};
+        assert_eq!(stored_keys(&declared), stored_keys(&stored));
+        let weights = text_weights(&index(declared), true);
+        assert_eq!(weights.get("bio").map(String::as_str), Some("1"));
+        assert_eq!(weights.get("name").map(String::as_str), Some("1"));
+        assert!(text_weights(&index(stored), false).is_empty());
+    }
+
+    #[test]
+    fn finds_no_differences_in_matching_indexes() {
+        let declared = index_with(doc! {"email": 1}, unique());
+        let existing = index_with(doc! {"email": 1_i64}, unique());
+        assert!(differences(&declared, &existing).is_empty());
+    }
+
+    #[test]
+    fn reports_changed_options() {
+        let declared = index_with(
+            doc! {"expires_at": 1},
+            IndexOptions::builder()
+                .expire_after(Duration::from_secs(0))
+                .build(),
+        );
+        let existing = index(doc! {"expires_at": 1});
+        assert_eq!(
+            differences(&declared, &existing),
+            ["expire_after: None -> Some(0ns)"]
+        );
+        let declared = index_with(doc! {"email": 1}, unique());
+        assert_eq!(
+            differences(&declared, &index(doc! {"email": 1})),
+            ["unique: false -> true"]
+        );
+    }
+
+    #[test]
+    fn plans_unchanged_changed_missing_and_undeclared_indexes() {
+        let declared = vec![
+            index_with(doc! {"email": 1}, unique()),
+            index(doc! {"created_at": -1}),
+            index(doc! {"last_name": 1}),
+        ];
+        let existing = vec![
+            index_with(
+                doc! {"_id": 1},
+                IndexOptions::builder().name(ID_INDEX.to_string()).build(),
+            ),
+            named(index_with(doc! {"email": 1}, unique())),
+            index_with(
+                doc! {"created_at": -1, "first_name": 1},
+                IndexOptions::builder()
+                    .name("created_at_-1".to_string())
+                    .build(),
+            ),
+            named(index(doc! {"legacy": 1})),
+        ];
+        let plan = plan("users", declared, existing);
+        assert_eq!(plan.unchanged, ["email_1"]);
+        assert_eq!(plan.changed.len(), 1);
+        assert_eq!(index_name(&plan.changed[0].0), "created_at_-1");
+        assert_eq!(plan.missing.len(), 1);
+        assert_eq!(index_name(&plan.missing[0]), "last_name_1");
+        assert_eq!(plan.undeclared, ["legacy_1"]);
+        assert!(!plan.is_in_sync());
+    }
+}
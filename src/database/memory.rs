@@ -0,0 +1,752 @@
+use crate::{
+    handlers::error_handler::Errors,
+    models::pagination::{
+        normalize_page, normalize_page_size, page_offset, CursorPage, KeysetQuery, Paginated,
+        PaginationMetadata,
+    },
+    traits::repository::{ModelStream, Repository, RepositoryModel},
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use mongodb::bson::{self, doc, oid::ObjectId, Bson, Document};
+use regex::RegexBuilder;
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    marker::PhantomData,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+// In-process backend used where no mongod is available. Models are kept as bson
+// documents keyed by id and filtered with a small subset of the query language.
+#[derive(Clone)]
+pub struct InMemoryRepository<M> {
+    documents: Arc<RwLock<BTreeMap<String, Document>>>,
+    model: PhantomData<fn() -> M>,
+}
+
+impl<M> Default for InMemoryRepository<M> {
+    fn default() -> Self {
+        Self {
+            documents: Arc::new(RwLock::new(BTreeMap::new())),
+            model: PhantomData,
+        }
+    }
+}
+
+impl<M: RepositoryModel> InMemoryRepository<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read_lock(&self) -> Result<RwLockReadGuard<'_, BTreeMap<String, Document>>, Errors> {
+        self.documents
+            .read()
+            .map_err(|error| Errors::InternalError(error.to_string()))
+    }
+
+    fn write_lock(&self) -> Result<RwLockWriteGuard<'_, BTreeMap<String, Document>>, Errors> {
+        self.documents
+            .write()
+            .map_err(|error| Errors::InternalError(error.to_string()))
+    }
+
+    fn filtered_documents(
+        &self,
+        filter: Option<Document>,
+        with_deleted: bool,
+    ) -> Result<Vec<Document>, Errors> {
+        let filter = filter.unwrap_or_default();
+        Ok(self
+            .read_lock()?
+            .values()
+            .filter(|document| with_deleted || !is_deleted::<M>(document))
+            .filter(|document| matches_filter(document, &filter))
+            .cloned()
+            .collect())
+    }
+
+    fn filtered(&self, filter: Option<Document>, with_deleted: bool) -> Result<Vec<M>, Errors> {
+        self.filtered_documents(filter, with_deleted)?
+            .into_iter()
+            .map(to_model)
+            .collect()
+    }
+
+    fn update_with(
+        &self,
+        filter: &Document,
+        update: &Document,
+        with_deleted: bool,
+    ) -> Result<Option<M>, Errors> {
+        let mut documents = self.write_lock()?;
+        let Some(document) = documents.values_mut().find(|document| {
+            (with_deleted || !is_deleted::<M>(document)) && matches_filter(document, filter)
+        }) else {
+            return Ok(None);
+        };
+        let mut updated = document.clone();
+        apply_update(&mut updated, update)?;
+        let mut model: M = to_model(updated)?;
+        model.set_updated_at(Utc::now().timestamp() as u64);
+        *document = to_document(&model)?;
+        Ok(Some(model))
+    }
+}
+
+fn is_deleted<M: RepositoryModel>(document: &Document) -> bool {
+    M::SOFT_DELETE && document.get_bool("is_deleted").unwrap_or(false)
+}
+
+// Evaluates the subset of the mongo query language used by the services:
+// logical operators, comparisons, `$in`, `$exists` and `$regex`.
+fn matches_filter(document: &Document, filter: &Document) -> bool {
+    filter.iter().all(|(key, condition)| match key.as_str() {
+        "$and" => sub_filters(condition).all(|filter| matches_filter(document, filter)),
+        "$or" => sub_filters(condition).any(|filter| matches_filter(document, filter)),
+        "$nor" => !sub_filters(condition).any(|filter| matches_filter(document, filter)),
+        _ => matches_condition(get_path(document, key), condition),
+    })
+}
+
+fn sub_filters(condition: &Bson) -> impl Iterator<Item = &Document> {
+    condition
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Bson::as_document)
+}
+
+fn get_path<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut current = document;
+    let mut parts = path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        let value = current.get(part)?;
+        if parts.peek().is_none() {
+            return Some(value);
+        }
+        current = value.as_document()?;
+    }
+    None
+}
+
+fn matches_condition(value: Option<&Bson>, condition: &Bson) -> bool {
+    match condition {
+        Bson::Document(operators) if is_operator_document(operators) => {
+            operators.iter().all(|(operator, operand)| {
+                matches_operator(value, operator, operand, operators.get_str("$options").ok())
+            })
+        }
+        Bson::RegularExpression(regex) => matches_regex(value, &regex.pattern, &regex.options),
+        _ => equals(value, condition),
+    }
+}
+
+fn is_operator_document(document: &Document) -> bool {
+    document
+        .keys()
+        .next()
+        .is_some_and(|key| key.starts_with('$'))
+}
+
+fn matches_operator(
+    value: Option<&Bson>,
+    operator: &str,
+    operand: &Bson,
+    options: Option<&str>,
+) -> bool {
+    let ordering = || value.and_then(|value| compare_bson(value, operand));
+    match operator {
+        "$eq" => equals(value, operand),
+        "$ne" => !equals(value, operand),
+        "$gt" => ordering() == Some(Ordering::Greater),
+        "$gte" => matches!(ordering(), Some(Ordering::Greater | Ordering::Equal)),
+        "$lt" => ordering() == Some(Ordering::Less),
+        "$lte" => matches!(ordering(), Some(Ordering::Less | Ordering::Equal)),
+        "$in" => operand
+            .as_array()
+            .is_some_and(|values| values.iter().any(|operand| equals(value, operand))),
+        "$nin" => !operand
+            .as_array()
+            .is_some_and(|values| values.iter().any(|operand| equals(value, operand))),
+        "$exists" => value.is_some() == operand.as_bool().unwrap_or(true),
+        "$elemMatch" => match (value, operand) {
+            (Some(Bson::Array(values)), Bson::Document(filter)) => values
+                .iter()
+                .filter_map(Bson::as_document)
+                .any(|document| matches_filter(document, filter)),
+            _ => false,
+        },
+        "$regex" => match operand {
+            Bson::String(pattern) => matches_regex(value, pattern, options.unwrap_or_default()),
+            Bson::RegularExpression(regex) => matches_regex(value, &regex.pattern, &regex.options),
+            _ => false,
+        },
+        "$options" => true,
+        "$not" => !matches_condition(value, operand),
+        _ => false,
+    }
+}
+
+fn matches_regex(value: Option<&Bson>, pattern: &str, options: &str) -> bool {
+    let Some(Bson::String(value)) = value else {
+        return false;
+    };
+    RegexBuilder::new(pattern)
+        .case_insensitive(options.contains('i'))
+        .multi_line(options.contains('m'))
+        .build()
+        .is_ok_and(|regex| regex.is_match(value))
+}
+
+fn equals(value: Option<&Bson>, operand: &Bson) -> bool {
+    match value {
+        None => operand == &Bson::Null,
+        Some(Bson::Array(values)) if !matches!(operand, Bson::Array(_)) => {
+            values.iter().any(|value| equals(Some(value), operand))
+        }
+        Some(value) => value == operand || compare_bson(value, operand) == Some(Ordering::Equal),
+    }
+}
+
+fn as_number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(number) => Some(*number as f64),
+        Bson::Int64(number) => Some(*number as f64),
+        Bson::Double(number) => Some(*number),
+        _ => None,
+    }
+}
+
+fn compare_bson(left: &Bson, right: &Bson) -> Option<Ordering> {
+    match (left, right) {
+        (Bson::String(left), Bson::String(right)) => Some(left.cmp(right)),
+        (Bson::Boolean(left), Bson::Boolean(right)) => Some(left.cmp(right)),
+        (Bson::DateTime(left), Bson::DateTime(right)) => Some(left.cmp(right)),
+        (Bson::ObjectId(left), Bson::ObjectId(right)) => Some(left.cmp(right)),
+        (Bson::Null, Bson::Null) => Some(Ordering::Equal),
+        _ => as_number(left)?.partial_cmp(&as_number(right)?),
+    }
+}
+
+fn sort_documents(documents: &mut [Document], sort: &Document) {
+    documents.sort_by(|left, right| {
+        sort.iter()
+            .map(|(key, direction)| {
+                let ordering = match (get_path(left, key), get_path(right, key)) {
+                    (Some(left), Some(right)) => {
+                        compare_bson(left, right).unwrap_or(Ordering::Equal)
+                    }
+                    (None, Some(_)) => Ordering::Less,
+                    (Some(_), None) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                };
+                if as_number(direction).unwrap_or(1.0) < 0.0 {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+// The document holding the last segment of a dotted `path` and that segment. With
+// `create` the documents in between are added like the server does, without it a
+// missing one means there is nothing to update. Paths into arrays are not supported.
+fn parent_mut<'a, 'p>(
+    document: &'a mut Document,
+    path: &'p str,
+    create: bool,
+) -> Result<Option<(&'a mut Document, &'p str)>, Errors> {
+    let (parents, field) = match path.rsplit_once('.') {
+        Some((parents, field)) => (Some(parents), field),
+        None => (None, path),
+    };
+    let mut current = document;
+    for part in parents.into_iter().flat_map(|parents| parents.split('.')) {
+        if create && !current.contains_key(part) {
+            current.insert(part, Document::new());
+        }
+        current = match current.get_mut(part) {
+            Some(Bson::Document(next)) => next,
+            _ if !create => return Ok(None),
+            _ => {
+                return Err(Errors::InternalError(format!(
+                    "Cannot update {} inside a non document field",
+                    path
+                )))
+            }
+        };
+    }
+    Ok(Some((current, field)))
+}
+
+fn field_mut<'a, 'p>(
+    document: &'a mut Document,
+    path: &'p str,
+) -> Result<(&'a mut Document, &'p str), Errors> {
+    parent_mut(document, path, true)?
+        .ok_or_else(|| Errors::InternalError(format!("Cannot update {}", path)))
+}
+
+fn apply_update(document: &mut Document, update: &Document) -> Result<(), Errors> {
+    for (operator, fields) in update {
+        let fields = fields
+            .as_document()
+            .ok_or_else(|| Errors::InternalError(format!("Invalid {} document", operator)))?;
+        match operator.as_str() {
+            "$set" => {
+                for (key, value) in fields {
+                    let (parent, field) = field_mut(document, key)?;
+                    parent.insert(field, value.clone());
+                }
+            }
+            "$unset" => {
+                for key in fields.keys() {
+                    if let Some((parent, field)) = parent_mut(document, key, false)? {
+                        parent.remove(field);
+                    }
+                }
+            }
+            "$inc" => {
+                for (key, by) in fields {
+                    let (parent, field) = field_mut(document, key)?;
+                    let value = match (parent.get(field), by) {
+                        (None | Some(Bson::Null), by) => by.clone(),
+                        (Some(Bson::Int32(value)), Bson::Int32(by)) => Bson::Int32(value + by),
+                        (Some(value), by) => match (as_number(value), as_number(by)) {
+                            (Some(value), Some(by))
+                                if value.fract() == 0.0 && by.fract() == 0.0 =>
+                            {
+                                Bson::Int64((value + by) as i64)
+                            }
+                            (Some(value), Some(by)) => Bson::Double(value + by),
+                            _ => {
+                                return Err(Errors::InternalError(format!(
+                                    "Cannot increment non numeric field {}",
+                                    key
+                                )))
+                            }
+                        },
+                    };
+                    parent.insert(field, value);
+                }
+            }
+            "$push" => {
+                for (key, value) in fields {
+                    let (parent, field) = field_mut(document, key)?;
+                    match parent.get_mut(field) {
+                        Some(Bson::Array(values)) => values.push(value.clone()),
+                        None | Some(Bson::Null) => {
+                            parent.insert(field, vec![value.clone()]);
+                        }
+                        Some(_) => {
+                            return Err(Errors::InternalError(format!(
+                                "Cannot push to non array field {}",
+                                key
+                            )))
+                        }
+                    }
+                }
+            }
+            "$addToSet" => {
+                for (key, value) in fields {
+                    let (parent, field) = field_mut(document, key)?;
+                    match parent.get_mut(field) {
+                        Some(Bson::Array(values)) => {
+                            if !values.contains(value) {
+                                values.push(value.clone());
+                            }
+                        }
+                        None | Some(Bson::Null) => {
+                            parent.insert(field, vec![value.clone()]);
+                        }
+                        Some(_) => {
+                            return Err(Errors::InternalError(format!(
+                                "Cannot add to non array field {}",
+                                key
+                            )))
+                        }
+                    }
+                }
+            }
+            "$pull" => {
+                for (key, condition) in fields {
+                    let Some((parent, field)) = parent_mut(document, key, false)? else {
+                        continue;
+                    };
+                    match parent.get_mut(field) {
+                        Some(Bson::Array(values)) => {
+                            values.retain(|value| !matches_condition(Some(value), condition))
+                        }
+                        None | Some(Bson::Null) => {}
+                        Some(_) => {
+                            return Err(Errors::InternalError(format!(
+                                "Cannot pull from non array field {}",
+                                key
+                            )))
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(Errors::InternalError(format!(
+                    "Unsupported update operator {}",
+                    operator
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_model<M: RepositoryModel>(document: Document) -> Result<M, Errors> {
+    bson::from_document(document).map_err(|error| Errors::Database(error.into()))
+}
+
+fn to_document<M: RepositoryModel>(model: &M) -> Result<Document, Errors> {
+    bson::to_document(model).map_err(|error| Errors::InternalError(error.to_string()))
+}
+
+#[async_trait]
+impl<M: RepositoryModel> Repository<M> for InMemoryRepository<M> {
+    async fn create(&self, model: M) -> Result<M, Errors> {
+        let mut model = model;
+        let id = ObjectId::new().to_string();
+        model.set_id(id.clone());
+        let current_timestamp = Utc::now().timestamp() as u64;
+        model.set_created_at(current_timestamp);
+        model.set_updated_at(current_timestamp);
+        let document = to_document(&model)?;
+        self.write_lock()?.insert(id, document);
+        Ok(model)
+    }
+
+    async fn get_by_id(&self, id: &str) -> Result<Option<M>, Errors> {
+        self.read_lock()?
+            .get(id)
+            .filter(|document| !is_deleted::<M>(document))
+            .cloned()
+            .map(to_model)
+            .transpose()
+    }
+
+    async fn find(&self, filter: Option<Document>) -> Result<Vec<M>, Errors> {
+        self.filtered(filter, false)
+    }
+
+    async fn update(&self, id: &str, update: Document) -> Result<Option<M>, Errors> {
+        self.update_with(&doc! {"_id": id}, &update, false)
+    }
+
+    async fn update_where(&self, filter: Document, update: Document) -> Result<Option<M>, Errors> {
+        self.update_with(&filter, &update, false)
+    }
+
+    async fn delete(&self, id: &str) -> Result<Option<M>, Errors> {
+        if !M::SOFT_DELETE {
+            return self.purge(id).await;
+        }
+        let current_timestamp = Utc::now().timestamp();
+        self.update_with(
+            &doc! {"_id": id},
+            &doc! {"$set": {"is_deleted": true, "deleted_at": current_timestamp}},
+            false,
+        )
+    }
+
+    async fn find_with_deleted(&self, filter: Option<Document>) -> Result<Vec<M>, Errors> {
+        self.filtered(filter, true)
+    }
+
+    async fn stream(&self, filter: Option<Document>) -> Result<ModelStream<M>, Errors> {
+        Ok(stream::iter(self.filtered(filter, false)?.into_iter().map(Ok)).boxed())
+    }
+
+    async fn stream_with_deleted(
+        &self,
+        filter: Option<Document>,
+    ) -> Result<ModelStream<M>, Errors> {
+        Ok(stream::iter(self.filtered(filter, true)?.into_iter().map(Ok)).boxed())
+    }
+
+    async fn restore(&self, id: &str) -> Result<Option<M>, Errors> {
+        if !M::SOFT_DELETE {
+            return Err(Errors::InternalError(
+                "Model does not support soft delete".to_string(),
+            ));
+        }
+        let is_deleted = match self.read_lock()?.get(id) {
+            Some(document) => is_deleted::<M>(document),
+            None => false,
+        };
+        if !is_deleted {
+            return Ok(None);
+        }
+        self.update_with(
+            &doc! {"_id": id},
+            &doc! {"$set": {"is_deleted": false, "deleted_at": Bson::Null}},
+            true,
+        )
+    }
+
+    async fn purge(&self, id: &str) -> Result<Option<M>, Errors> {
+        self.write_lock()?.remove(id).map(to_model).transpose()
+    }
+
+    async fn purge_where(&self, filter: Document) -> Result<Option<M>, Errors> {
+        let mut documents = self.write_lock()?;
+        let id = documents
+            .iter()
+            .find(|(_, document)| matches_filter(document, &filter))
+            .map(|(id, _)| id.clone());
+        id.and_then(|id| documents.remove(&id))
+            .map(to_model)
+            .transpose()
+    }
+
+    async fn count(&self, filter: Option<Document>) -> Result<u64, Errors> {
+        Ok(self.filtered_documents(filter, false)?.len() as u64)
+    }
+
+    async fn paginate(
+        &self,
+        filter: Option<Document>,
+        sort: Option<Document>,
+        page: Option<u64>,
+        page_size: Option<u64>,
+    ) -> Result<Paginated<M>, Errors> {
+        let page = normalize_page(page);
+        let page_size = normalize_page_size(page_size);
+        let mut documents = self.filtered_documents(filter, false)?;
+        if let Some(sort) = sort {
+            sort_documents(&mut documents, &sort);
+        }
+        let total_records = documents.len() as u64;
+        let skip = page_offset(page, page_size)?;
+        let data = documents
+            .into_iter()
+            .skip(skip as usize)
+            .take(page_size as usize)
+            .map(to_model)
+            .collect::<Result<Vec<M>, Errors>>()?;
+        Ok(Paginated {
+            data,
+            metadata: PaginationMetadata {
+                current_page: page,
+                page_size,
+                total_records,
+                has_next_page: skip + page_size < total_records,
+            },
+        })
+    }
+
+    async fn paginate_keyset(
+        &self,
+        filter: Option<Document>,
+        sort_field: &str,
+        descending: bool,
+        cursor: Option<&str>,
+        page_size: Option<u64>,
+    ) -> Result<CursorPage<M>, Errors> {
+        let query = KeysetQuery::new(filter, sort_field, descending, cursor, page_size)?;
+        let mut documents = self.filtered_documents(Some(query.filter.clone()), false)?;
+        sort_documents(&mut documents, &query.sort);
+        let rows = documents
+            .into_iter()
+            .take(query.limit as usize)
+            .map(to_model)
+            .collect::<Result<Vec<M>, Errors>>()?;
+        query.into_page(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> Document {
+        doc! {
+            "_id": "1",
+            "first_name": "Ada",
+            "age": 36,
+            "score": 2.5,
+            "tags": ["admin", "user"],
+            "identities": [{"provider": "google", "subject": "a"}],
+            "profile": {"city": "London"},
+            "deleted_at": Bson::Null,
+        }
+    }
+
+    fn matches(filter: Document) -> bool {
+        matches_filter(&user(), &filter)
+    }
+
+    #[test]
+    fn matches_equality_and_nested_paths() {
+        assert!(matches(doc! {"first_name": "Ada"}));
+        assert!(!matches(doc! {"first_name": "Grace"}));
+        assert!(matches(doc! {"profile.city": "London"}));
+        assert!(matches(doc! {"tags": "admin"}));
+        assert!(matches(doc! {"age": 36_i64}));
+        assert!(matches(doc! {"deleted_at": Bson::Null}));
+        assert!(matches(doc! {"missing": Bson::Null}));
+    }
+
+    #[test]
+    fn matches_comparison_operators() {
+        assert!(matches(doc! {"age": {"$eq": 36}}));
+        assert!(matches(doc! {"age": {"$ne": 35}}));
+        assert!(matches(doc! {"age": {"$gt": 35, "$lt": 37}}));
+        assert!(matches(doc! {"age": {"$gte": 36, "$lte": 36}}));
+        assert!(!matches(doc! {"age": {"$gt": 36}}));
+        assert!(matches(doc! {"score": {"$lt": 3}}));
+        assert!(!matches(doc! {"first_name": {"$gt": 1}}));
+    }
+
+    #[test]
+    fn matches_set_and_existence_operators() {
+        assert!(matches(doc! {"first_name": {"$in": ["Ada", "Grace"]}}));
+        assert!(!matches(doc! {"first_name": {"$nin": ["Ada"]}}));
+        assert!(matches(doc! {"tags": {"$in": ["user"]}}));
+        assert!(matches(doc! {"age": {"$exists": true}}));
+        assert!(matches(doc! {"missing": {"$exists": false}}));
+        assert!(matches(
+            doc! {"identities": {"$elemMatch": {"provider": "google", "subject": "a"}}}
+        ));
+        assert!(!matches(
+            doc! {"identities": {"$elemMatch": {"provider": "google", "subject": "b"}}}
+        ));
+    }
+
+    #[test]
+    fn matches_regex_and_negation() {
+        assert!(matches(
+            doc! {"first_name": {"$regex": "^ad", "$options": "i"}}
+        ));
+        assert!(!matches(doc! {"first_name": {"$regex": "^ad"}}));
+        assert!(matches(doc! {"first_name": {"$not": {"$eq": "Grace"}}}));
+        assert!(!matches(doc! {"age": {"$regex": "36"}}));
+    }
+
+    #[test]
+    fn matches_logical_operators() {
+        assert!(matches(doc! {"$and": [{"first_name": "Ada"}, {"age": 36}]}));
+        assert!(!matches(doc! {"$and": [{"first_name": "Ada"}, {"age": 1}]}));
+        assert!(matches(
+            doc! {"$or": [{"first_name": "Grace"}, {"age": 36}]}
+        ));
+        assert!(matches(
+            doc! {"$nor": [{"first_name": "Grace"}, {"age": 1}]}
+        ));
+        assert!(!matches(doc! {"$nor": [{"first_name": "Ada"}]}));
+    }
+
+    #[test]
+    fn applies_update_operators() {
+        let mut document = user();
+        apply_update(
+            &mut document,
+            &doc! {
+                "$set": {"first_name": "$last_name"},
+                "$unset": {"profile": ""},
+                "$inc": {"age": 1, "score": 0.5, "logins": 1},
+                "$push": {"tags": "owner", "history": "created"},
+            },
+        )
+        .unwrap();
+        assert_eq!(document.get_str("first_name").unwrap(), "$last_name");
+        assert!(!document.contains_key("profile"));
+        assert_eq!(document.get_i32("age").unwrap(), 37);
+        assert_eq!(document.get_f64("score").unwrap(), 3.0);
+        assert_eq!(document.get_i32("logins").unwrap(), 1);
+        assert_eq!(document.get_array("tags").unwrap().len(), 3);
+        assert_eq!(document.get_array("history").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn applies_update_operators_to_nested_paths() {
+        let mut document = user();
+        apply_update(
+            &mut document,
+            &doc! {
+                "$set": {"profile.city": "Paris", "settings.theme.name": "dark"},
+                "$unset": {"profile.country": "", "missing.field": ""},
+                "$inc": {"profile.visits": 1},
+                "$push": {"profile.tags": "new"},
+            },
+        )
+        .unwrap();
+        assert!(!document.contains_key("profile.city"));
+        assert_eq!(
+            document.get_document("profile").unwrap(),
+            &doc! {"city": "Paris", "visits": 1, "tags": ["new"]}
+        );
+        assert_eq!(
+            document.get_document("settings").unwrap(),
+            &doc! {"theme": {"name": "dark"}}
+        );
+        apply_update(&mut document, &doc! {"$unset": {"profile.city": ""}}).unwrap();
+        assert!(!document
+            .get_document("profile")
+            .unwrap()
+            .contains_key("city"));
+    }
+
+    #[test]
+    fn adds_to_and_pulls_from_arrays() {
+        let mut document = user();
+        apply_update(
+            &mut document,
+            &doc! {"$addToSet": {"tags": "admin", "roles": "owner"}},
+        )
+        .unwrap();
+        assert_eq!(document.get_array("tags").unwrap().len(), 2);
+        assert_eq!(document.get_array("roles").unwrap().len(), 1);
+        apply_update(&mut document, &doc! {"$addToSet": {"tags": "owner"}}).unwrap();
+        apply_update(
+            &mut document,
+            &doc! {"$pull": {"tags": "admin", "missing": "admin"}},
+        )
+        .unwrap();
+        assert_eq!(
+            document.get_array("tags").unwrap(),
+            &vec![Bson::from("user"), Bson::from("owner")]
+        );
+        apply_update(
+            &mut document,
+            &doc! {"$pull": {"tags": {"$in": ["user", "owner"]}}},
+        )
+        .unwrap();
+        assert!(document.get_array("tags").unwrap().is_empty());
+        assert!(apply_update(&mut document, &doc! {"$pull": {"age": 36}}).is_err());
+        assert!(apply_update(&mut document, &doc! {"$addToSet": {"age": 1}}).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_updates() {
+        let mut document = user();
+        assert!(apply_update(&mut document, &doc! {"$rename": {"age": "years"}}).is_err());
+        assert!(apply_update(&mut document, &doc! {"$inc": {"first_name": 1}}).is_err());
+        assert!(apply_update(&mut document, &doc! {"$push": {"age": 1}}).is_err());
+        assert!(apply_update(&mut document, &doc! {"$set": {"first_name.given": "Ada"}}).is_err());
+    }
+
+    #[test]
+    fn sorts_by_several_keys() {
+        let mut documents = vec![
+            doc! {"_id": "a", "last_name": "Hopper", "age": 40},
+            doc! {"_id": "b", "last_name": "Lovelace", "age": 36},
+            doc! {"_id": "c", "last_name": "Hopper", "age": 85},
+        ];
+        sort_documents(&mut documents, &doc! {"last_name": 1, "age": -1});
+        let ids: Vec<_> = documents
+            .iter()
+            .map(|document| document.get_str("_id").unwrap())
+            .collect();
+        assert_eq!(ids, ["c", "a", "b"]);
+    }
+}
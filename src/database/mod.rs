@@ -1,2 +1,8 @@
 pub mod core_service;
+pub mod health;
+pub mod indexes;
+pub mod memory;
+pub mod mongo_repository;
 pub mod mongodb;
+pub mod query;
+pub mod transaction;
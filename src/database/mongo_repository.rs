@@ -0,0 +1,134 @@
+use super::mongodb::MongoClient;
+use crate::{
+    handlers::error_handler::Errors,
+    models::pagination::{CursorPage, KeysetQuery, Paginated},
+    traits::repository::{ModelStream, Repository, RepositoryModel},
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument, UpdateModifications},
+};
+
+#[async_trait]
+impl<M: RepositoryModel> Repository<M> for MongoClient {
+    async fn create(&self, model: M) -> Result<M, Errors> {
+        let mut model = model;
+        self.create_one(M::COLLECTION_NAME, &mut model, None, None)
+            .await?;
+        Ok(model)
+    }
+
+    async fn get_by_id(&self, id: &str) -> Result<Option<M>, Errors> {
+        self.read_one::<M>(M::COLLECTION_NAME, doc! {"_id": id}, None, None)
+            .await
+    }
+
+    async fn find(&self, filter: Option<Document>) -> Result<Vec<M>, Errors> {
+        self.read_many::<M>(M::COLLECTION_NAME, filter, None, None)
+            .await
+    }
+
+    async fn update(&self, id: &str, update: Document) -> Result<Option<M>, Errors> {
+        Repository::<M>::update_where(self, doc! {"_id": id}, update).await
+    }
+
+    async fn update_where(&self, filter: Document, update: Document) -> Result<Option<M>, Errors> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        self.update_one::<M>(
+            M::COLLECTION_NAME.to_string(),
+            filter,
+            UpdateModifications::Document(update),
+            Some(options),
+            None,
+        )
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> Result<Option<M>, Errors> {
+        self.delete_one::<M>(M::COLLECTION_NAME.to_string(), doc! {"_id": id}, None, None)
+            .await
+    }
+
+    async fn find_with_deleted(&self, filter: Option<Document>) -> Result<Vec<M>, Errors> {
+        self.read_many_with_deleted::<M>(M::COLLECTION_NAME, filter, None, None)
+            .await
+    }
+
+    async fn stream(&self, filter: Option<Document>) -> Result<ModelStream<M>, Errors> {
+        let stream = self
+            .stream_many::<M>(M::COLLECTION_NAME, filter, None, None)
+            .await?;
+        Ok(stream.boxed())
+    }
+
+    async fn stream_with_deleted(
+        &self,
+        filter: Option<Document>,
+    ) -> Result<ModelStream<M>, Errors> {
+        let stream = self
+            .stream_many_with_deleted::<M>(M::COLLECTION_NAME, filter, None, None)
+            .await?;
+        Ok(stream.boxed())
+    }
+
+    async fn restore(&self, id: &str) -> Result<Option<M>, Errors> {
+        self.restore_one::<M>(M::COLLECTION_NAME.to_string(), doc! {"_id": id}, None)
+            .await
+    }
+
+    async fn purge(&self, id: &str) -> Result<Option<M>, Errors> {
+        self.purge_one::<M>(M::COLLECTION_NAME.to_string(), doc! {"_id": id}, None, None)
+            .await
+    }
+
+    async fn purge_where(&self, filter: Document) -> Result<Option<M>, Errors> {
+        self.purge_one::<M>(M::COLLECTION_NAME.to_string(), filter, None, None)
+            .await
+    }
+
+    async fn count(&self, filter: Option<Document>) -> Result<u64, Errors> {
+        MongoClient::count::<M>(self, M::COLLECTION_NAME, filter, None, None).await
+    }
+
+    async fn paginate(
+        &self,
+        filter: Option<Document>,
+        sort: Option<Document>,
+        page: Option<u64>,
+        page_size: Option<u64>,
+    ) -> Result<Paginated<M>, Errors> {
+        let mut sort = sort.unwrap_or_default();
+        if !sort.contains_key("_id") {
+            sort.insert("_id", 1);
+        }
+        let aggregate = vec![
+            doc! {"$match": filter.unwrap_or_default()},
+            doc! {"$sort": sort},
+        ];
+        self.query_read_paginated::<M>(
+            M::COLLECTION_NAME.to_string(),
+            aggregate,
+            page,
+            page_size,
+            None,
+            None,
+        )
+        .await
+    }
+
+    async fn paginate_keyset(
+        &self,
+        filter: Option<Document>,
+        sort_field: &str,
+        descending: bool,
+        cursor: Option<&str>,
+        page_size: Option<u64>,
+    ) -> Result<CursorPage<M>, Errors> {
+        let query = KeysetQuery::new(filter, sort_field, descending, cursor, page_size)?;
+        self.read_keyset::<M>(M::COLLECTION_NAME, query, None).await
+    }
+}
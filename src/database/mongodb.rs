@@ -1,5 +1,24 @@
 use crate::handlers::error_handler::Errors;
-use mongodb::Client;
+use mongodb::{
+    bson::doc,
+    event::cmap::{
+        CmapEventHandler, ConnectionCheckedInEvent, ConnectionCheckedOutEvent,
+        ConnectionCheckoutFailedEvent, ConnectionClosedEvent, ConnectionCreatedEvent,
+    },
+    options::{
+        ClientOptions, Compressor, ReadConcern, ReadPreference, SelectionCriteria, Tls, TlsOptions,
+        WriteConcern,
+    },
+    Client,
+};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 #[derive(Default, Clone)]
 pub struct Url(String);
@@ -25,18 +44,66 @@ impl DbName {
 #[derive(Default, Clone)]
 pub struct NoDbName;
 
+// Connection pool counters across every server, fed by the driver's CMAP events.
+#[derive(Default, Debug)]
+pub struct PoolStats {
+    open: AtomicI64,
+    in_use: AtomicI64,
+    checkout_failures: AtomicI64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct PoolStatsSnapshot {
+    pub open: i64,
+    pub in_use: i64,
+    pub idle: i64,
+    pub checkout_failures: i64,
+}
+
+impl PoolStats {
+    pub fn snapshot(&self) -> PoolStatsSnapshot {
+        let open = self.open.load(Ordering::Relaxed);
+        let in_use = self.in_use.load(Ordering::Relaxed);
+        PoolStatsSnapshot {
+            open,
+            in_use,
+            idle: (open - in_use).max(0),
+            checkout_failures: self.checkout_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl CmapEventHandler for PoolStats {
+    fn handle_connection_created_event(&self, _event: ConnectionCreatedEvent) {
+        self.open.fetch_add(1, Ordering::Relaxed);
+    }
+    fn handle_connection_closed_event(&self, _event: ConnectionClosedEvent) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+    fn handle_connection_checked_out_event(&self, _event: ConnectionCheckedOutEvent) {
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+    }
+    fn handle_connection_checked_in_event(&self, _event: ConnectionCheckedInEvent) {
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+    fn handle_connection_checkout_failed_event(&self, _event: ConnectionCheckoutFailedEvent) {
+        self.checkout_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 #[derive(Clone)]
 pub struct MongoClient {
     pub url: String,
     pub db_name: String,
     pub client: Client,
+    pub pool_stats: Arc<PoolStats>,
 }
 
 #[derive(Default, Clone)]
 pub struct MongoClientBuilder<U, D> {
     pub url: U,
     pub db_name: D,
-    pub client: Option<Client>,
+    pub options: ClientOptions,
 }
 
 impl MongoClientBuilder<NoUrl, NoDbName> {
@@ -46,23 +113,91 @@ impl MongoClientBuilder<NoUrl, NoDbName> {
 }
 
 impl MongoClientBuilder<Url, DbName> {
+    // Options set in the connection string are kept unless overridden by the builder.
     pub async fn url(url: Url, db_name: DbName) -> Result<Self, Errors> {
-        let client = Client::with_uri_str(url.0.clone())
+        let options = ClientOptions::parse(url.0.as_str())
             .await
-            .map_err(|_| Errors::InternalError(String::from("Invalid Mongo Uri!")))?;
+            .map_err(Errors::Database)?;
 
         Ok(Self {
-            client: Some(client),
+            options,
             db_name,
             url,
         })
     }
 
-    pub fn build(self) -> Result<MongoClient, Errors> {
+    pub fn app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.options.app_name = Some(app_name.into());
+        self
+    }
+
+    pub fn max_pool_size(mut self, max_pool_size: u32) -> Self {
+        self.options.max_pool_size = Some(max_pool_size);
+        self
+    }
+
+    pub fn min_pool_size(mut self, min_pool_size: u32) -> Self {
+        self.options.min_pool_size = Some(min_pool_size);
+        self
+    }
+
+    pub fn max_idle_time(mut self, max_idle_time: Duration) -> Self {
+        self.options.max_idle_time = Some(max_idle_time);
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.options.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn server_selection_timeout(mut self, server_selection_timeout: Duration) -> Self {
+        self.options.server_selection_timeout = Some(server_selection_timeout);
+        self
+    }
+
+    pub fn read_concern(mut self, read_concern: ReadConcern) -> Self {
+        self.options.read_concern = Some(read_concern);
+        self
+    }
+
+    pub fn write_concern(mut self, write_concern: WriteConcern) -> Self {
+        self.options.write_concern = Some(write_concern);
+        self
+    }
+
+    pub fn read_preference(mut self, read_preference: ReadPreference) -> Self {
+        self.options.selection_criteria = Some(SelectionCriteria::ReadPreference(read_preference));
+        self
+    }
+
+    pub fn compressors(mut self, compressors: Vec<Compressor>) -> Self {
+        self.options.compressors = Some(compressors);
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsOptions) -> Self {
+        self.options.tls = Some(Tls::Enabled(tls));
+        self
+    }
+
+    // Pings the server so an unreachable or misconfigured deployment fails at startup
+    // instead of on the first request.
+    pub async fn build(mut self) -> Result<MongoClient, Errors> {
+        let pool_stats = Arc::new(PoolStats::default());
+        self.options.cmap_event_handler = Some(pool_stats.clone());
+        let client = Client::with_options(self.options).map_err(Errors::Database)?;
+        client
+            .database(&self.db_name.0)
+            .run_command(doc! {"ping": 1}, None)
+            .await
+            .map_err(Errors::Database)?;
+
         Ok(MongoClient {
             url: self.url.0,
             db_name: self.db_name.0,
-            client: self.client.unwrap(),
+            client,
+            pool_stats,
         })
     }
 }
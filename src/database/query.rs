@@ -0,0 +1,387 @@
+use crate::handlers::error_handler::Errors;
+use mongodb::bson::{self, doc, Bson, Document, Regex};
+use serde::Serialize;
+use std::{marker::PhantomData, ops::Not};
+
+pub use actix_mongo_template_macros::ModelFields;
+
+// A stored field of `M` holding a `T`. Constants are generated by
+// `#[derive(ModelFields)]`, so a filter on a renamed or removed field no longer
+// compiles, and neither does comparing it with a value of the wrong type.
+pub struct Field<M, T> {
+    name: &'static str,
+    model: PhantomData<fn() -> (M, T)>,
+}
+
+impl<M, T> Clone for Field<M, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M, T> Copy for Field<M, T> {}
+
+// Fields `regex` can be used on.
+pub trait TextField {}
+impl TextField for String {}
+impl TextField for Option<String> {}
+
+// Fields `inc` can be used on.
+pub trait NumericField {}
+impl NumericField for i32 {}
+impl NumericField for i64 {}
+impl NumericField for u32 {}
+impl NumericField for u64 {}
+impl NumericField for f64 {}
+impl<T: NumericField> NumericField for Option<T> {}
+
+fn to_bson(value: &impl Serialize) -> Result<Bson, String> {
+    bson::to_bson(value).map_err(|error| error.to_string())
+}
+
+fn to_bson_array<V: Serialize>(values: impl IntoIterator<Item = V>) -> Result<Bson, String> {
+    values
+        .into_iter()
+        .map(|value| to_bson(&value))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Bson::Array)
+}
+
+impl<M, T> Field<M, T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            model: PhantomData,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    fn condition(self, condition: Result<Bson, String>) -> Filter<M> {
+        Filter::from_result(condition.map(|condition| doc! {self.name: condition}))
+    }
+    fn operator(self, operator: &str, operand: Result<Bson, String>) -> Filter<M> {
+        self.condition(operand.map(|operand| Bson::Document(doc! {operator: operand})))
+    }
+    pub fn exists(self, exists: bool) -> Filter<M> {
+        self.operator("$exists", Ok(Bson::Boolean(exists)))
+    }
+}
+
+impl<M, T: Serialize> Field<M, T> {
+    pub fn eq(self, value: impl Into<T>) -> Filter<M> {
+        self.operator("$eq", to_bson(&value.into()))
+    }
+    pub fn ne(self, value: impl Into<T>) -> Filter<M> {
+        self.operator("$ne", to_bson(&value.into()))
+    }
+    pub fn is_in<V: Into<T>>(self, values: impl IntoIterator<Item = V>) -> Filter<M> {
+        self.operator("$in", to_bson_array(values.into_iter().map(Into::into)))
+    }
+    pub fn not_in<V: Into<T>>(self, values: impl IntoIterator<Item = V>) -> Filter<M> {
+        self.operator("$nin", to_bson_array(values.into_iter().map(Into::into)))
+    }
+    pub fn gt(self, value: impl Into<T>) -> Filter<M> {
+        self.operator("$gt", to_bson(&value.into()))
+    }
+    pub fn gte(self, value: impl Into<T>) -> Filter<M> {
+        self.operator("$gte", to_bson(&value.into()))
+    }
+    pub fn lt(self, value: impl Into<T>) -> Filter<M> {
+        self.operator("$lt", to_bson(&value.into()))
+    }
+    pub fn lte(self, value: impl Into<T>) -> Filter<M> {
+        self.operator("$lte", to_bson(&value.into()))
+    }
+    // Inclusive on both ends.
+    pub fn between(self, low: impl Into<T>, high: impl Into<T>) -> Filter<M> {
+        let range = to_bson(&low.into()).and_then(|low| {
+            to_bson(&high.into()).map(|high| Bson::Document(doc! {"$gte": low, "$lte": high}))
+        });
+        self.condition(range)
+    }
+}
+
+impl<M, T: TextField> Field<M, T> {
+    // `pattern` is a regular expression, escape user input with `regex::escape`.
+    pub fn regex(self, pattern: impl Into<String>, case_insensitive: bool) -> Filter<M> {
+        let regex = Regex {
+            pattern: pattern.into(),
+            options: if case_insensitive { "i" } else { "" }.to_string(),
+        };
+        self.operator("$regex", Ok(Bson::RegularExpression(regex)))
+    }
+}
+
+impl<M, E: Serialize> Field<M, Vec<E>> {
+    // Matches arrays holding `value` among their elements.
+    pub fn contains(self, value: impl Into<E>) -> Filter<M> {
+        self.operator("$eq", to_bson(&value.into()))
+    }
+}
+
+impl<M, E> Field<M, Vec<E>> {
+    // Matches arrays with at least one element matching every condition in `filter`.
+    pub fn elem_match(self, filter: Filter<E>) -> Filter<M> {
+        self.operator("$elemMatch", filter.document.map(Bson::Document))
+    }
+}
+
+// A filter on `M`. Serialization errors are kept until `build` so conditions can
+// be chained without a `?` at every step.
+pub struct Filter<M> {
+    document: Result<Document, String>,
+    model: PhantomData<fn() -> M>,
+}
+
+impl<M> Default for Filter<M> {
+    fn default() -> Self {
+        Self::from_result(Ok(Document::new()))
+    }
+}
+
+impl<M> Filter<M> {
+    fn from_result(document: Result<Document, String>) -> Self {
+        Self {
+            document,
+            model: PhantomData,
+        }
+    }
+    fn combine(operator: &str, filters: impl IntoIterator<Item = Filter<M>>) -> Self {
+        let filters = filters
+            .into_iter()
+            .map(|filter| filter.document.map(Bson::Document))
+            .collect::<Result<Vec<_>, _>>();
+        Self::from_result(filters.map(|filters| doc! {operator: filters}))
+    }
+    pub fn and(filters: impl IntoIterator<Item = Filter<M>>) -> Self {
+        Self::combine("$and", filters)
+    }
+    pub fn or(filters: impl IntoIterator<Item = Filter<M>>) -> Self {
+        Self::combine("$or", filters)
+    }
+    pub fn nor(filters: impl IntoIterator<Item = Filter<M>>) -> Self {
+        Self::combine("$nor", filters)
+    }
+    pub fn build(self) -> Result<Document, Errors> {
+        self.document.map_err(Errors::InternalError)
+    }
+}
+
+// `!filter` matches the documents `filter` does not.
+impl<M> Not for Filter<M> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::nor([self])
+    }
+}
+
+// An operator update on `M`, `$set`, `$inc`, `$push`, `$addToSet`, `$pull` and
+// `$unset` only.
+pub struct Update<M> {
+    document: Result<Document, String>,
+    model: PhantomData<fn() -> M>,
+}
+
+impl<M> Default for Update<M> {
+    fn default() -> Self {
+        Self {
+            document: Ok(Document::new()),
+            model: PhantomData,
+        }
+    }
+}
+
+impl<M> Update<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn with(mut self, operator: &str, field: &'static str, value: Result<Bson, String>) -> Self {
+        self.document = self.document.and_then(|mut document| {
+            let value = value?;
+            match document.get_mut(operator) {
+                Some(Bson::Document(fields)) => {
+                    fields.insert(field, value);
+                }
+                _ => {
+                    document.insert(operator, doc! {field: value});
+                }
+            }
+            Ok(document)
+        });
+        self
+    }
+    pub fn set<T: Serialize>(self, field: Field<M, T>, value: impl Into<T>) -> Self {
+        self.with("$set", field.name, to_bson(&value.into()))
+    }
+    // Only optional fields can be removed, anything else would no longer deserialize.
+    pub fn unset<T>(self, field: Field<M, Option<T>>) -> Self {
+        self.with("$unset", field.name, Ok(Bson::String(String::new())))
+    }
+    pub fn inc<T: NumericField + Serialize>(self, field: Field<M, T>, by: impl Into<T>) -> Self {
+        self.with("$inc", field.name, to_bson(&by.into()))
+    }
+    pub fn push<E: Serialize>(self, field: Field<M, Vec<E>>, value: impl Into<E>) -> Self {
+        self.with("$push", field.name, to_bson(&value.into()))
+    }
+    // Appends `value` unless the array already holds it.
+    pub fn add_to_set<E: Serialize>(self, field: Field<M, Vec<E>>, value: impl Into<E>) -> Self {
+        self.with("$addToSet", field.name, to_bson(&value.into()))
+    }
+    // Removes every element equal to `value`.
+    pub fn pull<E: Serialize>(self, field: Field<M, Vec<E>>, value: impl Into<E>) -> Self {
+        self.with("$pull", field.name, to_bson(&value.into()))
+    }
+    pub fn build(self) -> Result<Document, Errors> {
+        self.document.map_err(Errors::InternalError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, ModelFields)]
+    struct Item {
+        #[serde(rename = "_id")]
+        id: String,
+        name: Option<String>,
+        count: u64,
+        tags: Vec<String>,
+        parts: Vec<Part>,
+        r#type: String,
+    }
+
+    #[derive(Serialize, ModelFields)]
+    struct Part {
+        kind: String,
+        size: i64,
+    }
+
+    #[test]
+    fn derives_stored_names() {
+        assert_eq!(Item::ID.name(), "_id");
+        assert_eq!(Item::NAME.name(), "name");
+        assert_eq!(Item::TYPE.name(), "type");
+        assert_eq!(Part::KIND.name(), "kind");
+    }
+
+    #[test]
+    fn builds_comparisons() {
+        assert_eq!(
+            Item::ID.eq("a").build().unwrap(),
+            doc! {"_id": {"$eq": "a"}}
+        );
+        assert_eq!(
+            Item::NAME.ne(None::<String>).build().unwrap(),
+            doc! {"name": {"$ne": null}}
+        );
+        assert_eq!(
+            Item::ID.is_in(["a", "b"]).build().unwrap(),
+            doc! {"_id": {"$in": ["a", "b"]}}
+        );
+        assert_eq!(
+            Item::ID.not_in(["a"]).build().unwrap(),
+            doc! {"_id": {"$nin": ["a"]}}
+        );
+        assert_eq!(
+            Item::COUNT.gt(1u64).build().unwrap(),
+            doc! {"count": {"$gt": 1_i64}}
+        );
+        assert_eq!(
+            Item::COUNT.between(1u64, 5u64).build().unwrap(),
+            doc! {"count": {"$gte": 1_i64, "$lte": 5_i64}}
+        );
+        assert_eq!(
+            Item::NAME.exists(false).build().unwrap(),
+            doc! {"name": {"$exists": false}}
+        );
+    }
+
+    #[test]
+    fn builds_array_and_text_conditions() {
+        assert_eq!(
+            Item::TAGS.contains("new").build().unwrap(),
+            doc! {"tags": {"$eq": "new"}}
+        );
+        assert_eq!(
+            Item::PARTS
+                .elem_match(Filter::and([Part::KIND.eq("bolt"), Part::SIZE.lt(3)]))
+                .build()
+                .unwrap(),
+            doc! {"parts": {"$elemMatch": {"$and": [
+                {"kind": {"$eq": "bolt"}},
+                {"size": {"$lt": 3_i64}},
+            ]}}}
+        );
+        let Bson::Document(condition) = Item::NAME
+            .regex("^a", true)
+            .build()
+            .unwrap()
+            .remove("name")
+            .unwrap()
+        else {
+            panic!("expected a condition document");
+        };
+        assert_eq!(
+            condition.get("$regex"),
+            Some(&Bson::RegularExpression(Regex {
+                pattern: "^a".to_string(),
+                options: "i".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn combines_filters() {
+        let filter = Filter::or([Item::ID.eq("a"), !Item::COUNT.lte(2u64)]);
+        assert_eq!(
+            filter.build().unwrap(),
+            doc! {"$or": [
+                {"_id": {"$eq": "a"}},
+                {"$nor": [{"count": {"$lte": 2_i64}}]},
+            ]}
+        );
+        assert_eq!(Filter::<Item>::default().build().unwrap(), doc! {});
+    }
+
+    #[test]
+    fn keeps_serialization_errors_until_build() {
+        // Values above `i64::MAX` have no BSON representation.
+        let filter = Filter::and([Item::ID.eq("a"), Item::COUNT.eq(u64::MAX)]);
+        assert!(matches!(filter.build(), Err(Errors::InternalError(_))));
+        let update = Update::new().inc(Item::COUNT, u64::MAX).set(Item::ID, "a");
+        assert!(matches!(update.build(), Err(Errors::InternalError(_))));
+    }
+
+    #[test]
+    fn groups_updates_by_operator() {
+        let update = Update::new()
+            .set(Item::ID, "a")
+            .set(Item::TYPE, "b")
+            .unset(Item::NAME)
+            .inc(Item::COUNT, 2u64)
+            .push(Item::TAGS, "x")
+            .add_to_set(
+                Item::PARTS,
+                Part {
+                    kind: "nut".to_string(),
+                    size: 1,
+                },
+            )
+            .pull(Item::TAGS, "y");
+        assert_eq!(
+            update.build().unwrap(),
+            doc! {
+                "$set": {"_id": "a", "type": "b"},
+                "$unset": {"name": ""},
+                "$inc": {"count": 2_i64},
+                "$push": {"tags": "x"},
+                "$addToSet": {"parts": {"kind": "nut", "size": 1_i64}},
+                "$pull": {"tags": "y"},
+            }
+        );
+        assert_eq!(Update::<Item>::new().build().unwrap(), doc! {});
+    }
+}
@@ -0,0 +1,188 @@
+use super::mongodb::MongoClient;
+use crate::handlers::error_handler::Errors;
+use futures::future::BoxFuture;
+use log::warn;
+use mongodb::{
+    error::{
+        ErrorKind, WriteFailure, TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT,
+    },
+    ClientSession,
+};
+use std::time::{Duration, Instant};
+
+// Same budget the driver's own `with_transaction` uses before giving up on retries.
+const TRANSACTION_RETRY_TIMEOUT: Duration = Duration::from_secs(120);
+// The commit ran out of its time limit, retrying it would only run out again.
+const MAX_TIME_MS_EXPIRED: i32 = 50;
+
+fn has_label(error: &Errors, label: &str) -> bool {
+    matches!(error, Errors::Database(error) if error.contains_label(label))
+}
+
+fn server_code(error: &Errors) -> Option<i32> {
+    let Errors::Database(error) = error else {
+        return None;
+    };
+    match error.kind.as_ref() {
+        ErrorKind::Command(error) => Some(error.code),
+        ErrorKind::Write(WriteFailure::WriteConcernError(error)) => Some(error.code),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Retry {
+    Commit,
+    Transaction,
+    GiveUp,
+}
+
+// The retry rules of the driver transactions spec for a failed commit.
+fn after_commit_error(
+    unknown_commit_result: bool,
+    transient: bool,
+    code: Option<i32>,
+    elapsed: Duration,
+) -> Retry {
+    if elapsed >= TRANSACTION_RETRY_TIMEOUT {
+        Retry::GiveUp
+    } else if unknown_commit_result && code != Some(MAX_TIME_MS_EXPIRED) {
+        Retry::Commit
+    } else if transient {
+        Retry::Transaction
+    } else {
+        Retry::GiveUp
+    }
+}
+
+impl MongoClient {
+    // Runs `operation` inside a transaction and commits it. The session has to be
+    // passed to every core_service call made by the operation, e.g.
+    //
+    //     client.with_transaction(|client, session| Box::pin(async move {
+    //         client.create_one(UserModel::COLLECTION_NAME, &mut user, None, Some(session)).await
+    //     })).await
+    //
+    // The whole operation is retried on `TransientTransactionError` and the commit
+    // alone on `UnknownTransactionCommitResult`, unless it hit its time limit, so
+    // the operation may run more than once and must not have side effects outside
+    // the database.
+    pub async fn with_transaction<T, F>(&self, mut operation: F) -> Result<T, Errors>
+    where
+        F: for<'s> FnMut(
+            &'s MongoClient,
+            &'s mut ClientSession,
+        ) -> BoxFuture<'s, Result<T, Errors>>,
+    {
+        let mut session = self
+            .client
+            .start_session(None)
+            .await
+            .map_err(Errors::Database)?;
+        let started_at = Instant::now();
+        'transaction: loop {
+            session
+                .start_transaction(None)
+                .await
+                .map_err(Errors::Database)?;
+            let value = match operation(self, &mut session).await {
+                Ok(value) => value,
+                Err(error) => {
+                    // The server may already have aborted it, an abort error is
+                    // not worth hiding the original one for.
+                    let _ = session.abort_transaction().await;
+                    if has_label(&error, TRANSIENT_TRANSACTION_ERROR)
+                        && started_at.elapsed() < TRANSACTION_RETRY_TIMEOUT
+                    {
+                        warn!("Retrying transaction after transient error: {}", error);
+                        continue 'transaction;
+                    }
+                    return Err(error);
+                }
+            };
+            loop {
+                let error = match session.commit_transaction().await {
+                    Ok(()) => return Ok(value),
+                    Err(error) => Errors::Database(error),
+                };
+                match after_commit_error(
+                    has_label(&error, UNKNOWN_TRANSACTION_COMMIT_RESULT),
+                    has_label(&error, TRANSIENT_TRANSACTION_ERROR),
+                    server_code(&error),
+                    started_at.elapsed(),
+                ) {
+                    Retry::Commit => {
+                        warn!("Retrying transaction commit: {}", error);
+                    }
+                    Retry::Transaction => {
+                        warn!("Retrying transaction after transient error: {}", error);
+                        continue 'transaction;
+                    }
+                    Retry::GiveUp => return Err(error),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::{bson, error::CommandError};
+
+    fn command_error(code: i32) -> Errors {
+        let error: CommandError =
+            bson::from_document(bson::doc! {"code": code, "codeName": "", "errmsg": ""}).unwrap();
+        Errors::Database(ErrorKind::Command(error).into())
+    }
+
+    #[test]
+    fn retries_the_commit_when_its_result_is_unknown() {
+        let elapsed = Duration::from_secs(1);
+        assert_eq!(
+            after_commit_error(true, false, None, elapsed),
+            Retry::Commit
+        );
+        assert_eq!(
+            after_commit_error(true, true, Some(91), elapsed),
+            Retry::Commit
+        );
+    }
+
+    #[test]
+    fn does_not_retry_a_commit_that_exceeded_its_time_limit() {
+        let code = server_code(&command_error(MAX_TIME_MS_EXPIRED));
+        assert_eq!(code, Some(MAX_TIME_MS_EXPIRED));
+        let elapsed = Duration::from_secs(1);
+        assert_eq!(
+            after_commit_error(true, false, code, elapsed),
+            Retry::GiveUp
+        );
+        assert_eq!(
+            after_commit_error(true, true, code, elapsed),
+            Retry::Transaction
+        );
+    }
+
+    #[test]
+    fn restarts_the_transaction_on_transient_errors_only() {
+        let elapsed = Duration::from_secs(1);
+        assert_eq!(
+            after_commit_error(false, true, None, elapsed),
+            Retry::Transaction
+        );
+        assert_eq!(
+            after_commit_error(false, false, Some(11000), elapsed),
+            Retry::GiveUp
+        );
+    }
+
+    #[test]
+    fn gives_up_once_the_retry_budget_is_spent() {
+        assert_eq!(
+            after_commit_error(true, true, None, TRANSACTION_RETRY_TIMEOUT),
+            Retry::GiveUp
+        );
+        assert_eq!(server_code(&Errors::InternalError(String::new())), None);
+    }
+}
@@ -1,15 +1,245 @@
+use crate::{
+    config::{AppConfig, Profile},
+    handlers::request_id,
+};
 use actix_web::{
-    error::ResponseError,
-    http::{header::ContentType, StatusCode},
-    HttpResponse,
+    error::{JsonPayloadError, PathError, QueryPayloadError, ResponseError},
+    http::{
+        header::{self, ContentType, HeaderValue},
+        StatusCode,
+    },
+    HttpRequest, HttpResponse,
 };
 use derive_more::Display;
+use mongodb::error::{ErrorKind, WriteFailure};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Display, Debug)]
+const PROBLEM_JSON: &str = "application/problem+json";
+const RETRY_AFTER_SECONDS: u64 = 5;
+const DUPLICATE_KEY_CODES: [i32; 3] = [11000, 11001, 12582];
+// Server codes for failovers, shutdowns and exceeded time limits, which clear up on retry.
+const UNAVAILABLE_CODES: [i32; 14] = [
+    6, 7, 50, 64, 89, 91, 189, 262, 9001, 10107, 11600, 11602, 13435, 13436,
+];
+
+#[derive(Display, Debug)]
 pub enum Errors {
     InternalError(String),
     HttpError(HttpErrors),
+    Database(mongodb::error::Error),
+}
+
+#[derive(Debug, PartialEq)]
+enum DatabaseFailure {
+    DuplicateKey(Option<String>),
+    Unavailable,
+    Deserialization,
+    Other,
+}
+
+// `E11000 duplicate key error collection: db.users index: email_1 dup key: { email: "..." }`
+fn duplicate_key_field(message: &str) -> Option<String> {
+    let key = message.split_once("dup key: {")?.1;
+    let field = key.split_once(':')?.0.trim();
+    (!field.is_empty()).then(|| field.to_string())
+}
+
+fn classify_database_error(error: &mongodb::error::Error) -> DatabaseFailure {
+    let duplicate_key = |code: i32, message: &str| {
+        DUPLICATE_KEY_CODES
+            .contains(&code)
+            .then(|| DatabaseFailure::DuplicateKey(duplicate_key_field(message)))
+    };
+    let failure = match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(error)) => {
+            duplicate_key(error.code, &error.message)
+        }
+        ErrorKind::Write(WriteFailure::WriteConcernError(_)) => Some(DatabaseFailure::Unavailable),
+        ErrorKind::BulkWrite(failure) => failure
+            .write_errors
+            .iter()
+            .flatten()
+            .find_map(|error| duplicate_key(error.code, &error.message))
+            .or_else(|| {
+                failure
+                    .write_concern_error
+                    .as_ref()
+                    .map(|_| DatabaseFailure::Unavailable)
+            }),
+        ErrorKind::Command(error) => duplicate_key(error.code, &error.message).or_else(|| {
+            UNAVAILABLE_CODES
+                .contains(&error.code)
+                .then_some(DatabaseFailure::Unavailable)
+        }),
+        ErrorKind::ServerSelection { .. }
+        | ErrorKind::Io(_)
+        | ErrorKind::ConnectionPoolCleared { .. }
+        | ErrorKind::DnsResolve { .. }
+        | ErrorKind::Shutdown => Some(DatabaseFailure::Unavailable),
+        ErrorKind::BsonDeserialization(_) => Some(DatabaseFailure::Deserialization),
+        _ => None,
+    };
+    failure.unwrap_or_else(|| {
+        if error.contains_label("RetryableWriteError")
+            || error.contains_label("TransientTransactionError")
+        {
+            DatabaseFailure::Unavailable
+        } else {
+            DatabaseFailure::Other
+        }
+    })
+}
+
+fn duplicate_key_message(field: Option<&str>) -> String {
+    match field {
+        Some(field) => format!("A record with this {} already exists", field),
+        None => "A record with these values already exists".to_string(),
+    }
+}
+
+const UNAVAILABLE_MESSAGE: &str = "The database is temporarily unavailable, retry later";
+
+// Same codes as `Errors::code`, for the per-document errors of a bulk write.
+pub fn write_error_code(server_code: i32) -> &'static str {
+    if DUPLICATE_KEY_CODES.contains(&server_code) {
+        "duplicate_key"
+    } else if UNAVAILABLE_CODES.contains(&server_code) {
+        "database_unavailable"
+    } else {
+        "database_error"
+    }
+}
+
+// Same messages as `Errors::public_message`, for the per-document errors of a bulk write.
+pub fn write_error_message(server_code: i32, message: &str) -> Option<String> {
+    if DUPLICATE_KEY_CODES.contains(&server_code) {
+        Some(duplicate_key_message(
+            duplicate_key_field(message).as_deref(),
+        ))
+    } else if UNAVAILABLE_CODES.contains(&server_code) {
+        Some(UNAVAILABLE_MESSAGE.to_string())
+    } else {
+        None
+    }
+}
+
+// The cause can leak internals, so production only gets a generic message.
+fn internal_message(error: &impl ToString) -> String {
+    match AppConfig::get().profile {
+        Profile::Production => "Internal server error".to_string(),
+        Profile::Development => error.to_string(),
+    }
+}
+
+// Every error response has this shape. With `errors.problem_details` enabled the
+// RFC 7807 members are added and the content type is `application/problem+json`.
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub problem_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+fn build_error_response(
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<Value>,
+) -> HttpResponse {
+    let problem_details = AppConfig::get().errors.problem_details;
+    let body = ErrorBody {
+        problem_type: problem_details.then_some("about:blank"),
+        title: problem_details.then(|| status.canonical_reason()).flatten(),
+        status: problem_details.then_some(status.as_u16()),
+        code,
+        message,
+        details,
+        request_id: request_id::current(),
+    };
+    let mut response = HttpResponse::build(status);
+    if problem_details {
+        response.content_type(PROBLEM_JSON);
+    } else {
+        response.insert_header(ContentType::json());
+    }
+    response.json(body)
+}
+
+impl Errors {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InternalError(_) => "internal_error",
+            Self::HttpError(error) => error.code(),
+            Self::Database(error) => match classify_database_error(error) {
+                DatabaseFailure::DuplicateKey(_) => "duplicate_key",
+                DatabaseFailure::Unavailable => "database_unavailable",
+                DatabaseFailure::Deserialization => "database_deserialization_error",
+                DatabaseFailure::Other => "database_error",
+            },
+        }
+    }
+
+    // The message the error envelope shows, `None` for internal errors, whose cause
+    // is only shown outside production.
+    pub fn public_message(&self) -> Option<String> {
+        match self {
+            Self::InternalError(_) => None,
+            Self::HttpError(error) => Some(error.message()),
+            Self::Database(error) => match classify_database_error(error) {
+                DatabaseFailure::DuplicateKey(field) => {
+                    Some(duplicate_key_message(field.as_deref()))
+                }
+                DatabaseFailure::Unavailable => Some(UNAVAILABLE_MESSAGE.to_string()),
+                DatabaseFailure::Deserialization | DatabaseFailure::Other => None,
+            },
+        }
+    }
+
+    fn database_error_response(&self, error: &mongodb::error::Error) -> HttpResponse {
+        let (message, details) = match classify_database_error(error) {
+            DatabaseFailure::DuplicateKey(field) => (
+                duplicate_key_message(field.as_deref()),
+                field.map(|field| json!({"field": field})),
+            ),
+            DatabaseFailure::Unavailable => {
+                log::warn!(
+                    "Database unavailable (request {}): {}",
+                    request_id::current().unwrap_or_default(),
+                    error
+                );
+                let mut response = build_error_response(
+                    self.status_code(),
+                    self.code(),
+                    UNAVAILABLE_MESSAGE.to_string(),
+                    Some(json!({"retry_after_seconds": RETRY_AFTER_SECONDS})),
+                );
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECONDS));
+                return response;
+            }
+            DatabaseFailure::Deserialization | DatabaseFailure::Other => {
+                log::error!(
+                    "Database error (request {}): {}",
+                    request_id::current().unwrap_or_default(),
+                    error
+                );
+                (internal_message(error), None)
+            }
+        };
+        build_error_response(self.status_code(), self.code(), message, details)
+    }
 }
 
 impl ResponseError for Errors {
@@ -17,27 +247,102 @@ impl ResponseError for Errors {
         match self {
             Self::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::HttpError(error) => error.status_code(),
+            Self::Database(error) => match classify_database_error(error) {
+                DatabaseFailure::DuplicateKey(_) => StatusCode::CONFLICT,
+                DatabaseFailure::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+                DatabaseFailure::Deserialization | DatabaseFailure::Other => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            },
         }
     }
     fn error_response(&self) -> HttpResponse {
         match self {
             Self::InternalError(error) => {
-                let body = serde_json::to_string(&error).unwrap();
-                HttpResponse::build(self.status_code())
-                    .insert_header(ContentType::json())
-                    .body(body)
+                log::error!(
+                    "Internal error (request {}): {}",
+                    request_id::current().unwrap_or_default(),
+                    error
+                );
+                build_error_response(
+                    self.status_code(),
+                    self.code(),
+                    internal_message(error),
+                    None,
+                )
             }
             Self::HttpError(error) => error.error_response(),
+            Self::Database(error) => self.database_error_response(error),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FieldError {
+    pub code: String,
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize, Display, Debug)]
 pub enum HttpErrors {
     BadRequest,
     Unauthorized,
+    Forbidden,
     Message(String),
     NotFound,
+    TokenExpired,
+    InvalidToken,
+    #[display(fmt = "Validation failed")]
+    Validation(BTreeMap<String, Vec<FieldError>>),
+    InvalidPayload(String),
+    InvalidQuery(String),
+    InvalidPath(String),
+    UnsupportedMediaType,
+    PayloadTooLarge,
+}
+
+impl HttpErrors {
+    // Stable, machine-readable identifiers. Clients match on these, so never rename one.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest => "bad_request",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::Message(_) => "invalid_request",
+            Self::NotFound => "not_found",
+            Self::TokenExpired => "token_expired",
+            Self::InvalidToken => "invalid_token",
+            Self::Validation(_) => "validation_failed",
+            Self::InvalidPayload(_) => "invalid_payload",
+            Self::InvalidQuery(_) => "invalid_query",
+            Self::InvalidPath(_) => "invalid_path",
+            Self::UnsupportedMediaType => "unsupported_media_type",
+            Self::PayloadTooLarge => "payload_too_large",
+        }
+    }
+    pub fn message(&self) -> String {
+        match self {
+            Self::BadRequest => "The request is malformed".to_string(),
+            Self::Unauthorized => "Authentication is required".to_string(),
+            Self::Forbidden => "You are not allowed to perform this action".to_string(),
+            Self::Message(message) => message.clone(),
+            Self::NotFound => "The requested resource was not found".to_string(),
+            Self::TokenExpired => "The token has expired".to_string(),
+            Self::InvalidToken => "The token is invalid".to_string(),
+            Self::Validation(_) => "The request has invalid fields".to_string(),
+            Self::InvalidPayload(message) => message.clone(),
+            Self::InvalidQuery(message) => message.clone(),
+            Self::InvalidPath(message) => message.clone(),
+            Self::UnsupportedMediaType => "The request body must be application/json".to_string(),
+            Self::PayloadTooLarge => "The request body is too large".to_string(),
+        }
+    }
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            Self::Validation(fields) => Some(json!({ "fields": fields })),
+            _ => None,
+        }
+    }
 }
 
 impl ResponseError for HttpErrors {
@@ -45,14 +350,258 @@ impl ResponseError for HttpErrors {
         match self {
             Self::BadRequest => StatusCode::BAD_REQUEST,
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
             Self::Message(_) => StatusCode::BAD_REQUEST,
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::TokenExpired => StatusCode::UNAUTHORIZED,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InvalidPayload(_) | Self::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidPath(_) => StatusCode::NOT_FOUND,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
         }
     }
     fn error_response(&self) -> HttpResponse {
-        let body = serde_json::to_string(&self).unwrap();
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::json())
-            .body(body)
+        build_error_response(
+            self.status_code(),
+            self.code(),
+            self.message(),
+            self.details(),
+        )
+    }
+}
+
+// Registered through `web::JsonConfig` so malformed bodies get the same envelope.
+pub fn json_error_handler(error: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let error = match error {
+        JsonPayloadError::ContentType => HttpErrors::UnsupportedMediaType,
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            HttpErrors::PayloadTooLarge
+        }
+        JsonPayloadError::Deserialize(error) => HttpErrors::InvalidPayload(error.to_string()),
+        error => HttpErrors::InvalidPayload(error.to_string()),
+    };
+    Errors::HttpError(error).into()
+}
+
+// Registered through `web::QueryConfig`, e.g. `?page=abc`.
+pub fn query_error_handler(error: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    Errors::HttpError(HttpErrors::InvalidQuery(error.to_string())).into()
+}
+
+// Registered through `web::PathConfig`. A segment that does not parse names no
+// resource, so it stays a 404 like an unknown path.
+pub fn path_error_handler(error: PathError, _req: &HttpRequest) -> actix_web::Error {
+    Errors::HttpError(HttpErrors::InvalidPath(error.to_string())).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{helpers::enums::Role, models::user::UserListQuery};
+    use actix_web::{test, web, App};
+    use mongodb::{
+        bson::{self, doc},
+        error::{CommandError, WriteConcernError, WriteError},
+    };
+
+    async fn get(path: &str) -> (u16, Value) {
+        AppConfig::init_for_tests();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+                .app_data(web::PathConfig::default().error_handler(path_error_handler))
+                .route(
+                    "/users",
+                    web::get()
+                        .to(|_: web::Query<UserListQuery>| async { HttpResponse::Ok().finish() }),
+                )
+                .route(
+                    "/roles/{role}",
+                    web::get().to(|_: web::Path<Role>| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(path).to_request()).await;
+        let status = response.status().as_u16();
+        (status, test::read_body_json(response).await)
+    }
+
+    async fn respond(path: &str, request_id: Option<&str>) -> (u16, Option<String>, Value) {
+        AppConfig::init_for_tests();
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(request_id::propagate)
+                .route(
+                    "/internal",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(Errors::InternalError(
+                            "mongodb://admin:hunter2@db:27017 refused".to_string(),
+                        ))
+                    }),
+                )
+                .route(
+                    "/missing",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(Errors::HttpError(HttpErrors::NotFound))
+                    }),
+                ),
+        )
+        .await;
+        let mut request = test::TestRequest::get().uri(path);
+        if let Some(request_id) = request_id {
+            request = request.insert_header((request_id::REQUEST_ID_HEADER, request_id));
+        }
+        let response = test::call_service(&app, request.to_request()).await;
+        let status = response.status().as_u16();
+        let header = response
+            .headers()
+            .get(request_id::REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        (status, header, test::read_body_json(response).await)
+    }
+
+    #[actix_web::test]
+    async fn errors_share_one_envelope() {
+        let (status, _, body) = respond("/missing", None).await;
+        assert_eq!(status, 404);
+        let mut keys: Vec<_> = body.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, ["code", "message", "request_id"]);
+        assert_eq!(body["code"], "not_found");
+        assert!(body["message"].is_string());
+    }
+
+    // The test configuration uses the production profile.
+    #[actix_web::test]
+    async fn production_hides_internal_messages() {
+        let (status, _, body) = respond("/internal", None).await;
+        assert_eq!(status, 500);
+        assert_eq!(body["code"], "internal_error");
+        assert_eq!(body["message"], "Internal server error");
+        assert!(!body.to_string().contains("hunter2"));
+    }
+
+    #[actix_web::test]
+    async fn request_id_matches_the_header() {
+        let (_, header, body) = respond("/missing", Some("client-req-42")).await;
+        assert_eq!(header.as_deref(), Some("client-req-42"));
+        assert_eq!(body["request_id"], "client-req-42");
+
+        // Malformed ids are replaced, in the header and the body alike.
+        let (_, header, body) = respond("/internal", Some("bad id!")).await;
+        let header = header.unwrap();
+        assert_ne!(header, "bad id!");
+        assert_eq!(body["request_id"], header.as_str());
+    }
+
+    fn command_error(code: i32, message: &str) -> mongodb::error::Error {
+        let error: CommandError =
+            bson::from_document(doc! {"code": code, "errmsg": message}).unwrap();
+        ErrorKind::Command(error).into()
+    }
+
+    fn write_error(code: i32, message: &str) -> mongodb::error::Error {
+        let error: WriteError =
+            bson::from_document(doc! {"code": code, "errmsg": message}).unwrap();
+        ErrorKind::Write(WriteFailure::WriteError(error)).into()
+    }
+
+    fn classified(error: mongodb::error::Error) -> (u16, &'static str) {
+        AppConfig::init_for_tests();
+        let error = Errors::Database(error);
+        (error.status_code().as_u16(), error.code())
+    }
+
+    #[actix_web::test]
+    async fn duplicate_keys_are_conflicts() {
+        let message =
+            "E11000 duplicate key error collection: db.users index: email_1 dup key: { email: \"ada@example.com\" }";
+        assert_eq!(
+            classified(write_error(11000, message)),
+            (409, "duplicate_key")
+        );
+        assert_eq!(
+            classified(command_error(11001, message)),
+            (409, "duplicate_key")
+        );
+        let error = Errors::Database(write_error(11000, message));
+        assert_eq!(
+            error.public_message().as_deref(),
+            Some("A record with this email already exists")
+        );
+        assert_eq!(
+            classify_database_error(&write_error(12582, "E12582 duplicate key")),
+            DatabaseFailure::DuplicateKey(None)
+        );
+    }
+
+    #[actix_web::test]
+    async fn connection_failures_are_unavailable() {
+        let unavailable = (503, "database_unavailable");
+        // Nothing listens on port 9, so selecting a server times out.
+        let client =
+            mongodb::Client::with_uri_str("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=50")
+                .await
+                .unwrap();
+        let selection = client
+            .database("test")
+            .run_command(doc! {"ping": 1}, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(*selection.kind, ErrorKind::ServerSelection { .. }));
+        assert_eq!(classified(selection), unavailable);
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert_eq!(classified(reset.into()), unavailable);
+        assert_eq!(classified(ErrorKind::Shutdown.into()), unavailable);
+        // ShutdownInProgress during a failover.
+        assert_eq!(classified(command_error(91, "shutting down")), unavailable);
+        let concern: WriteConcernError =
+            bson::from_document(doc! {"code": 64, "errmsg": "waiting for replication timed out"})
+                .unwrap();
+        let concern = ErrorKind::Write(WriteFailure::WriteConcernError(concern));
+        assert_eq!(classified(concern.into()), unavailable);
+
+        let response = Errors::Database(command_error(91, "shutting down")).error_response();
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "5");
+    }
+
+    #[actix_web::test]
+    async fn other_failures_are_internal() {
+        assert_eq!(
+            classified(command_error(2, "BadValue")),
+            (500, "database_error")
+        );
+        assert_eq!(
+            classified(write_error(121, "Document failed validation")),
+            (500, "database_error")
+        );
+        let malformed = bson::from_document::<FieldError>(doc! {"code": 1}).unwrap_err();
+        assert_eq!(
+            classified(malformed.into()),
+            (500, "database_deserialization_error")
+        );
+        assert_eq!(
+            Errors::Database(command_error(2, "BadValue")).public_message(),
+            None
+        );
+    }
+
+    #[actix_web::test]
+    async fn malformed_queries_get_the_error_envelope() {
+        let (status, body) = get("/users?page=abc").await;
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "invalid_query");
+        assert!(body["message"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn unparsable_path_segments_get_the_error_envelope() {
+        let (status, body) = get("/roles/Superuser").await;
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "invalid_path");
     }
 }
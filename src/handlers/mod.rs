@@ -1 +1,2 @@
 pub mod error_handler;
+pub mod request_id;
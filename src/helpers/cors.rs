@@ -0,0 +1,134 @@
+use crate::config::{AppConfig, Profile};
+use actix_cors::Cors;
+
+// `https://*.example.com` matches any subdomain of example.com over https, but
+// not example.com itself.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let origin = origin.to_ascii_lowercase();
+    match pattern.split_once("*.") {
+        Some((scheme, domain)) => origin
+            .strip_prefix(scheme)
+            .and_then(|host| host.strip_suffix(domain))
+            .and_then(|subdomain| subdomain.strip_suffix('.'))
+            .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains('/')),
+        None => pattern == origin,
+    }
+}
+
+// Builds the CORS layer for a route scope, falling back to the top level policy
+// when the scope has no override. Development without origins stays permissive.
+pub fn cors(scope: Option<&str>) -> Cors {
+    let config = AppConfig::get();
+    let policy = config.cors.policy(scope);
+    if config.profile == Profile::Development && policy.allowed_origins.is_empty() {
+        return Cors::permissive();
+    }
+
+    let mut cors = Cors::default()
+        .allowed_methods(policy.allowed_methods.iter().map(String::as_str))
+        .max_age(policy.max_age);
+    if policy.allowed_origins.iter().any(|origin| origin == "*") {
+        cors = cors.allow_any_origin();
+    } else {
+        let origins = policy.allowed_origins.clone();
+        cors = cors.allowed_origin_fn(move |origin, _| {
+            origin.to_str().is_ok_and(|origin| {
+                origins
+                    .iter()
+                    .any(|pattern| origin_matches(pattern, origin))
+            })
+        });
+    }
+    if policy.allowed_headers.iter().any(|header| header == "*") {
+        cors = cors.allow_any_header();
+    } else {
+        cors = cors.allowed_headers(policy.allowed_headers.iter().map(String::as_str));
+    }
+    if policy.exposed_headers.iter().any(|header| header == "*") {
+        cors = cors.expose_any_header();
+    } else if !policy.exposed_headers.is_empty() {
+        cors = cors.expose_headers(policy.exposed_headers.iter().map(String::as_str));
+    }
+    if policy.supports_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_exact_origins() {
+        assert!(origin_matches(
+            "https://app.example.com",
+            "https://app.example.com"
+        ));
+        assert!(origin_matches(
+            "https://App.Example.com",
+            "https://app.example.COM"
+        ));
+        assert!(!origin_matches(
+            "https://app.example.com",
+            "https://app.example.com.evil.io"
+        ));
+        assert!(!origin_matches(
+            "https://app.example.com",
+            "https://other.example.com"
+        ));
+    }
+
+    #[test]
+    fn matches_subdomains_of_wildcards() {
+        let pattern = "https://*.example.com";
+        assert!(origin_matches(pattern, "https://app.example.com"));
+        assert!(origin_matches(pattern, "https://a.b.example.com"));
+        assert!(!origin_matches(pattern, "https://example.com"));
+        assert!(!origin_matches(pattern, "https://.example.com"));
+        assert!(!origin_matches(pattern, "https://evilexample.com"));
+        assert!(!origin_matches(pattern, "https://example.com.evil.io"));
+    }
+
+    #[test]
+    fn requires_the_same_port() {
+        assert!(origin_matches(
+            "http://localhost:3000",
+            "http://localhost:3000"
+        ));
+        assert!(!origin_matches(
+            "http://localhost:3000",
+            "http://localhost:3001"
+        ));
+        assert!(!origin_matches("http://localhost:3000", "http://localhost"));
+        assert!(origin_matches(
+            "https://*.example.com:8443",
+            "https://app.example.com:8443"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://app.example.com:8443"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com:8443",
+            "https://app.example.com"
+        ));
+    }
+
+    #[test]
+    fn requires_the_same_scheme() {
+        assert!(!origin_matches(
+            "https://app.example.com",
+            "http://app.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "http://app.example.com"
+        ));
+        assert!(!origin_matches(
+            "http://*.example.com",
+            "https://app.example.com"
+        ));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::{
+    config::AppConfig,
+    handlers::error_handler::{Errors, HttpErrors},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use mongodb::bson::Document;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn invalid_cursor() -> Errors {
+    Errors::HttpError(HttpErrors::Message("Invalid cursor".to_string()))
+}
+
+fn mac() -> HmacSha256 {
+    HmacSha256::new_from_slice(AppConfig::get().auth.cursor_secret.as_bytes())
+        .expect("HMAC accepts any key length")
+}
+
+// Cursors are `base64(bson payload).base64(hmac)` so clients cannot forge positions.
+pub fn encode_cursor(payload: &Document) -> Result<String, Errors> {
+    let mut bytes = Vec::new();
+    payload
+        .to_writer(&mut bytes)
+        .map_err(|error| Errors::InternalError(error.to_string()))?;
+    let mut mac = mac();
+    mac.update(&bytes);
+    let signature = mac.finalize().into_bytes();
+    Ok(format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(&bytes),
+        URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<Document, Errors> {
+    let (payload, signature) = cursor.split_once('.').ok_or_else(invalid_cursor)?;
+    let bytes = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| invalid_cursor())?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| invalid_cursor())?;
+    let mut mac = mac();
+    mac.update(&bytes);
+    mac.verify_slice(&signature).map_err(|_| invalid_cursor())?;
+    Document::from_reader(bytes.as_slice()).map_err(|_| invalid_cursor())
+}
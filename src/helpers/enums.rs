@@ -1,17 +1,12 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub enum UserStatus {
+    #[default]
     Active,
     Inactive,
 }
 
-impl Default for UserStatus {
-    fn default() -> Self {
-        Self::Active
-    }
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Gender {
     Male,
@@ -19,14 +14,51 @@ pub enum Gender {
     Other,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OAuthType {
     Google,
     Facebook,
     None,
 }
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl OAuthType {
+    pub fn from_path(provider: &str) -> Option<Self> {
+        match provider {
+            "google" => Some(Self::Google),
+            "facebook" => Some(Self::Facebook),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
 pub enum JwtTokenType {
+    #[default]
     Access,
     Refresh,
 }
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    Admin,
+    User,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Permission {
+    ReadUsers,
+    ManageUsers,
+    ManageRoles,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Self::Admin => &[
+                Permission::ReadUsers,
+                Permission::ManageUsers,
+                Permission::ManageRoles,
+            ],
+            Self::User => &[],
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::handlers::{error_handler::Errors, request_id};
+use actix_web::{
+    http::header::{self, HeaderValue},
+    web::Bytes,
+    HttpRequest, HttpResponse,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+
+pub const NDJSON: &str = "application/x-ndjson";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamFormat {
+    JsonArray,
+    Ndjson,
+}
+
+impl StreamFormat {
+    // NDJSON when the client asks for it in `Accept`, a JSON array otherwise.
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let accepts_ndjson = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| {
+                accept.split(',').any(|media_type| {
+                    let media_type = media_type.split(';').next().unwrap_or_default().trim();
+                    media_type.eq_ignore_ascii_case(NDJSON)
+                        || media_type.eq_ignore_ascii_case("application/ndjson")
+                })
+            });
+        if accepts_ndjson {
+            Self::Ndjson
+        } else {
+            Self::JsonArray
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::JsonArray => "application/json",
+            Self::Ndjson => NDJSON,
+        }
+    }
+}
+
+fn encode_item<T: Serialize>(item: &T, prefix: &[u8], suffix: &[u8]) -> Result<Bytes, Errors> {
+    let mut bytes = prefix.to_vec();
+    serde_json::to_writer(&mut bytes, item)
+        .map_err(|error| Errors::InternalError(error.to_string()))?;
+    bytes.extend_from_slice(suffix);
+    Ok(Bytes::from(bytes))
+}
+
+// Writes every item as soon as the stream yields it, so the response never holds
+// more than one item in memory. The status is sent before the first item, so an
+// error halfway aborts the connection and the client sees a truncated body.
+pub fn json_stream<T, S>(items: S, format: StreamFormat) -> HttpResponse
+where
+    T: Serialize,
+    S: Stream<Item = Result<T, Errors>> + 'static,
+{
+    let request_id = request_id::current().unwrap_or_default();
+    let items = items.enumerate().map(move |(index, item)| {
+        let (prefix, suffix): (&[u8], &[u8]) = match format {
+            StreamFormat::JsonArray if index == 0 => (b"", b""),
+            StreamFormat::JsonArray => (b",", b""),
+            StreamFormat::Ndjson => (b"", b"\n"),
+        };
+        item.and_then(|item| encode_item(&item, prefix, suffix))
+            .map_err(|error| {
+                log::error!(
+                    "Aborting streamed response (request {}): {}",
+                    request_id,
+                    error
+                );
+                actix_web::Error::from(error)
+            })
+    });
+    let (opening, closing): (&'static [u8], &'static [u8]) = match format {
+        StreamFormat::JsonArray => (b"[", b"]"),
+        StreamFormat::Ndjson => (b"", b""),
+    };
+    let body = stream::once(async move { Ok(Bytes::from_static(opening)) })
+        .chain(items)
+        .chain(stream::once(async move { Ok(Bytes::from_static(closing)) }));
+    HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(format.content_type()),
+        ))
+        .streaming(body)
+}
@@ -0,0 +1,146 @@
+use crate::{config::JwtConfig, handlers::error_handler::Errors};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use std::{collections::HashMap, fs, str::FromStr, sync::OnceLock};
+
+static JWT_KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding_key: EncodingKey,
+}
+
+pub struct VerificationKey {
+    pub algorithm: Algorithm,
+    pub decoding_key: DecodingKey,
+}
+
+// The active signing key plus every key, active or retired, that tokens may
+// still be verified with. Keeping retired keys lets sessions survive a rotation.
+pub struct JwtKeys {
+    pub signing: SigningKey,
+    pub verification: HashMap<String, VerificationKey>,
+}
+
+fn config_error(message: impl Into<String>) -> Errors {
+    Errors::InternalError(message.into())
+}
+
+fn parse_algorithm(algorithm: &str) -> Result<Algorithm, Errors> {
+    Algorithm::from_str(algorithm.trim())
+        .map_err(|_| config_error(format!("Unsupported JWT algorithm {}", algorithm)))
+}
+
+fn is_hmac(algorithm: Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    )
+}
+
+fn read_pem(path: &str, name: &str) -> Result<Vec<u8>, Errors> {
+    if path.is_empty() {
+        return Err(config_error(format!("{} is not configured", name)));
+    }
+    fs::read(path)
+        .map_err(|error| config_error(format!("Cannot read {} {}: {}", name, path, error)))
+}
+
+fn encoding_key(config: &JwtConfig, algorithm: Algorithm) -> Result<EncodingKey, Errors> {
+    if is_hmac(algorithm) {
+        if config.secret.is_empty() {
+            return Err(config_error("jwt.secret is not configured"));
+        }
+        return Ok(EncodingKey::from_secret(config.secret.as_bytes()));
+    }
+    let pem = read_pem(&config.private_key_path, "jwt.private_key_path")?;
+    let key = match algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(&pem),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+        _ => EncodingKey::from_rsa_pem(&pem),
+    };
+    key.map_err(|error| config_error(format!("Invalid JWT private key: {}", error)))
+}
+
+fn decoding_key(algorithm: Algorithm, source: &str, name: &str) -> Result<DecodingKey, Errors> {
+    if is_hmac(algorithm) {
+        if source.is_empty() {
+            return Err(config_error(format!("{} is not configured", name)));
+        }
+        return Ok(DecodingKey::from_secret(source.as_bytes()));
+    }
+    let pem = read_pem(source, name)?;
+    let key = match algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+        _ => DecodingKey::from_rsa_pem(&pem),
+    };
+    key.map_err(|error| config_error(format!("Invalid JWT public key {}: {}", name, error)))
+}
+
+impl JwtKeys {
+    pub fn from_config(config: &JwtConfig) -> Result<Self, Errors> {
+        let algorithm = parse_algorithm(&config.algorithm)?;
+        let signing = SigningKey {
+            kid: config.key_id.clone(),
+            algorithm,
+            encoding_key: encoding_key(config, algorithm)?,
+        };
+        let mut verification = HashMap::new();
+        let public_source = if is_hmac(algorithm) {
+            config.secret.as_str()
+        } else {
+            config.public_key_path.as_str()
+        };
+        verification.insert(
+            signing.kid.clone(),
+            VerificationKey {
+                algorithm,
+                decoding_key: decoding_key(algorithm, public_source, "jwt.public_key_path")?,
+            },
+        );
+        for key in &config.verification_keys {
+            let algorithm = parse_algorithm(&key.algorithm)?;
+            let source = if is_hmac(algorithm) {
+                key.secret.as_str()
+            } else {
+                key.public_key_path.as_str()
+            };
+            verification.insert(
+                key.kid.clone(),
+                VerificationKey {
+                    algorithm,
+                    decoding_key: decoding_key(algorithm, source, &key.kid)?,
+                },
+            );
+        }
+        Ok(Self {
+            signing,
+            verification,
+        })
+    }
+
+    pub fn init(config: &JwtConfig) -> Result<(), Errors> {
+        let keys = Self::from_config(config)?;
+        JWT_KEYS
+            .set(keys)
+            .map_err(|_| config_error("JWT keys are already initialized"))
+    }
+
+    #[cfg(test)]
+    pub fn init_for_tests() {
+        let config = &crate::config::AppConfig::init_for_tests().jwt;
+        JWT_KEYS.get_or_init(|| Self::from_config(config).expect("test JWT keys"));
+    }
+
+    pub fn get() -> Result<&'static Self, Errors> {
+        JWT_KEYS
+            .get()
+            .ok_or_else(|| config_error("JWT keys are not initialized"))
+    }
+
+    // Tokens without a `kid` predate key ids and are checked against the signing key.
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&VerificationKey> {
+        self.verification.get(kid.unwrap_or(&self.signing.kid))
+    }
+}
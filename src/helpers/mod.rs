@@ -1 +1,7 @@
+pub mod cors;
+pub mod cursor;
 pub mod enums;
+pub mod json_stream;
+pub mod jwt_keys;
+pub mod password;
+pub mod validators;
@@ -0,0 +1,49 @@
+use crate::handlers::error_handler::Errors;
+use actix_web::web;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // Verified against when the user does not exist so that lookups for unknown
+    // accounts take as long as a real password check.
+    static ref DUMMY_PASSWORD_HASH: String =
+        hash_password_blocking("dummy-password").unwrap_or_default();
+}
+
+fn hash_password_blocking(password: &str) -> Result<String, Errors> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|error| Errors::InternalError(error.to_string()))
+}
+
+fn verify_password_blocking(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub async fn hash_password(password: String) -> Result<String, Errors> {
+    web::block(move || hash_password_blocking(&password))
+        .await
+        .map_err(|error| Errors::InternalError(error.to_string()))?
+}
+
+pub async fn verify_password(password: String, password_hash: Option<String>) -> bool {
+    web::block(move || match password_hash {
+        Some(password_hash) => verify_password_blocking(&password, &password_hash),
+        None => {
+            verify_password_blocking(&password, &DUMMY_PASSWORD_HASH);
+            false
+        }
+    })
+    .await
+    .unwrap_or(false)
+}
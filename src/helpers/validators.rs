@@ -0,0 +1,81 @@
+use crate::handlers::error_handler::{Errors, FieldError, HttpErrors};
+use lazy_static::lazy_static;
+use mongodb::bson::oid::ObjectId;
+use regex::Regex;
+use std::collections::BTreeMap;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+lazy_static! {
+    // Letters in any script, plus the separators that appear in real names.
+    static ref NAME_REGEX: Regex = Regex::new(r"^[\p{L}\p{M}][\p{L}\p{M} '.-]*$").unwrap();
+}
+
+pub fn validate_object_id(id: &str) -> Result<(), Errors> {
+    ObjectId::parse_str(id)
+        .map(|_| ())
+        .map_err(|_| Errors::HttpError(HttpErrors::Message(format!("Invalid id {}", id))))
+}
+
+pub fn validate_not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        let mut error = ValidationError::new("not_blank");
+        error.message = Some("must not be blank".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+pub fn validate_name(value: &str) -> Result<(), ValidationError> {
+    validate_not_blank(value)?;
+    if !NAME_REGEX.is_match(value.trim()) {
+        let mut error = ValidationError::new("name");
+        error.message =
+            Some("must only contain letters, spaces, hyphens, apostrophes and periods".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Flattens nested struct and list errors into `parent.child` / `items[0].name` paths.
+fn collect_field_errors(
+    prefix: &str,
+    errors: &ValidationErrors,
+    fields: &mut BTreeMap<String, Vec<FieldError>>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                fields
+                    .entry(path)
+                    .or_default()
+                    .extend(errors.iter().map(|error| {
+                        FieldError {
+                            code: error.code.to_string(),
+                            message: error
+                                .message
+                                .as_ref()
+                                .map(|message| message.to_string())
+                                .unwrap_or_else(|| format!("failed {} validation", error.code)),
+                        }
+                    }));
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(&path, errors, fields),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    collect_field_errors(&format!("{}[{}]", path, index), errors, fields);
+                }
+            }
+        }
+    }
+}
+
+pub fn validation_error(errors: &ValidationErrors) -> Errors {
+    let mut fields = BTreeMap::new();
+    collect_field_errors("", errors, &mut fields);
+    Errors::HttpError(HttpErrors::Validation(fields))
+}
@@ -1,9 +1,18 @@
-use actix_cors::Cors;
-use actix_web::{get, middleware::Logger, web, App, HttpResponse, HttpServer, Responder};
-use database::mongodb::{DbName, MongoClient, MongoClientBuilder, Url};
+use actix_web::{middleware::Logger, web, App, HttpServer};
+use config::{AppConfig, IndexSyncMode, IndexesConfig};
+use database::{
+    indexes,
+    mongodb::{DbName, MongoClient, MongoClientBuilder, Url},
+};
 
 use env_logger::Env;
-use handlers::error_handler::Errors;
+use handlers::{
+    error_handler::{json_error_handler, path_error_handler, query_error_handler, Errors},
+    request_id,
+};
+use helpers::{cors::cors, jwt_keys::JwtKeys};
+use services::health_service::HealthRegistry;
+use std::{env, time::Duration};
 
 pub mod config;
 pub mod database;
@@ -14,48 +23,113 @@ pub mod routes;
 pub mod services;
 pub mod traits;
 
-use crate::config::{DATABASE_NAME, MONGO_URI};
+async fn build_mongo_client(config: &AppConfig) -> Result<MongoClient, Errors> {
+    let mongo = &config.mongo;
+    let url = Url::new(mongo.uri.clone());
+    let db_name = DbName::new(mongo.database.clone());
+    let invalid = |error: String| Errors::InternalError(error);
 
-#[get("/health-check")]
-async fn health_check() -> impl Responder {
-    HttpResponse::Ok().body("Ok")
+    let mut builder = MongoClientBuilder::<Url, DbName>::url(url, db_name).await?;
+    if let Some(app_name) = &mongo.app_name {
+        builder = builder.app_name(app_name);
+    }
+    if let Some(max_pool_size) = mongo.max_pool_size {
+        builder = builder.max_pool_size(max_pool_size);
+    }
+    if let Some(min_pool_size) = mongo.min_pool_size {
+        builder = builder.min_pool_size(min_pool_size);
+    }
+    if let Some(max_idle_time) = mongo.max_idle_time_ms {
+        builder = builder.max_idle_time(Duration::from_millis(max_idle_time));
+    }
+    if let Some(connect_timeout) = mongo.connect_timeout_ms {
+        builder = builder.connect_timeout(Duration::from_millis(connect_timeout));
+    }
+    if let Some(server_selection_timeout) = mongo.server_selection_timeout_ms {
+        builder = builder.server_selection_timeout(Duration::from_millis(server_selection_timeout));
+    }
+    if let Some(read_concern) = mongo.read_concern().map_err(invalid)? {
+        builder = builder.read_concern(read_concern);
+    }
+    if let Some(write_concern) = mongo.write_concern() {
+        builder = builder.write_concern(write_concern);
+    }
+    if let Some(read_preference) = mongo.read_preference().map_err(invalid)? {
+        builder = builder.read_preference(read_preference);
+    }
+    let compressors = mongo.compressors().map_err(invalid)?;
+    if !compressors.is_empty() {
+        builder = builder.compressors(compressors);
+    }
+    if let Some(tls) = mongo.tls() {
+        builder = builder.tls(tls);
+    }
+    builder.build().await
 }
 
-async fn build_mongo_client() -> Result<MongoClient, Errors> {
-    let url = Url::new(MONGO_URI.to_string());
-    let db_name = DbName::new(DATABASE_NAME.to_string());
-
-    MongoClientBuilder::<Url, DbName>::url(url, db_name)
-        .await?
-        .build()
+// E.g. a unique index that cannot be built because stored documents already
+// violate it. The message names the index and the offending key.
+fn index_sync_error(error: Errors) -> std::io::Error {
+    log::error!("Index sync failed: {}", error);
+    std::io::Error::other(format!("Index sync failed: {}", error))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    config::load_env();
-    env_logger::init_from_env(Env::default().default_filter_or("info"));
-    let mongo_client = build_mongo_client()
+    let config = AppConfig::init().unwrap_or_else(|error| panic!("{}", error));
+    env_logger::init_from_env(Env::default().default_filter_or(config.logging.level.as_str()));
+    let mongo_client = build_mongo_client(config)
         .await
         .expect("Database connection error!");
+    // `sync-indexes [--dry-run] [--drop]` syncs the declared indexes and exits.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("sync-indexes") {
+        let indexes = IndexesConfig {
+            sync: if args.iter().any(|arg| arg == "--dry-run") {
+                IndexSyncMode::DryRun
+            } else {
+                IndexSyncMode::Apply
+            },
+            drop: config.indexes.drop || args.iter().any(|arg| arg == "--drop"),
+        };
+        for plan in indexes::sync_all(&mongo_client, &indexes)
+            .await
+            .map_err(index_sync_error)?
+        {
+            println!("{}", plan);
+        }
+        return Ok(());
+    }
+    indexes::sync_all(&mongo_client, &config.indexes)
+        .await
+        .map_err(index_sync_error)?;
+    JwtKeys::init(&config.jwt).expect("JWT key configuration error!");
 
-    HttpServer::new(move || {
+    let health_registry = web::Data::new(HealthRegistry::new().register(mongo_client.clone()));
+    let mut server = HttpServer::new(move || {
         let logger = Logger::default();
         App::new()
+            .wrap_fn(request_id::propagate)
             .wrap(logger)
-            .wrap(Logger::new("%a %{User-Agent}i"))
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allow_any_header()
-                    .allow_any_method()
-                    .supports_credentials()
-                    .max_age(3600),
-            )
+            .wrap(Logger::new("%a %{User-Agent}i %{x-request-id}o"))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+            .app_data(web::PathConfig::default().error_handler(path_error_handler))
             .app_data(web::Data::new(mongo_client.clone()))
-            .service(health_check)
+            .app_data(health_registry.clone())
+            .service(
+                web::resource("/health-check")
+                    .wrap(cors(None))
+                    .route(web::get().to(routes::health_routes::health_check)),
+            )
+            .service(routes::health_routes::routes())
             .service(routes::routes())
-    })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    });
+    if let Some(workers) = config.server.workers {
+        server = server.workers(workers);
+    }
+    server
+        .bind((config.server.host.as_str(), config.server.port))?
+        .run()
+        .await
 }
@@ -0,0 +1,57 @@
+use crate::helpers::validators::{validate_name, validate_not_blank};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub const MIN_PASSWORD_LENGTH: u64 = 8;
+pub const MAX_PASSWORD_LENGTH: u64 = 128;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct RegisterModel {
+    #[validate(
+        length(min = 1, max = 100, message = "must be between 1 and 100 characters"),
+        custom(function = "validate_name")
+    )]
+    pub first_name: String,
+    #[validate(
+        length(min = 1, max = 100, message = "must be between 1 and 100 characters"),
+        custom(function = "validate_name")
+    )]
+    pub last_name: String,
+    #[validate(email(message = "must be a valid email address"))]
+    pub email: String,
+    #[validate(length(
+        min = MIN_PASSWORD_LENGTH,
+        max = MAX_PASSWORD_LENGTH,
+        message = "must be between 8 and 128 characters"
+    ))]
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct LoginModel {
+    #[validate(custom(function = "validate_not_blank"))]
+    pub email: String,
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TokenPairModel {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+}
+
+impl TokenPairModel {
+    pub fn bearer(access_token: String, refresh_token: String) -> Self {
+        Self {
+            access_token,
+            refresh_token,
+            token_type: "Bearer".to_string(),
+        }
+    }
+}
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
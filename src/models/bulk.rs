@@ -0,0 +1,148 @@
+use crate::handlers::error_handler::Errors;
+use mongodb::{
+    bson::{Bson, Document},
+    options::UpdateModifications,
+    results::UpdateResult,
+};
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkWriteMode {
+    // Stops at the first failure, later operations are reported as skipped.
+    #[default]
+    Ordered,
+    // Attempts every operation regardless of failures, in no particular order.
+    Unordered,
+}
+
+#[derive(Debug)]
+pub enum BulkOperation<M> {
+    InsertOne(M),
+    UpdateOne {
+        filter: Document,
+        update: UpdateModifications,
+    },
+    UpdateMany {
+        filter: Document,
+        update: UpdateModifications,
+    },
+    UpsertOne {
+        filter: Document,
+        update: UpdateModifications,
+    },
+    DeleteOne {
+        filter: Document,
+    },
+    DeleteMany {
+        filter: Document,
+    },
+}
+
+// One entry per submitted operation, at the same index.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum WriteStatus {
+    Inserted {
+        id: String,
+    },
+    Updated {
+        matched_count: u64,
+        modified_count: u64,
+        upserted_id: Option<String>,
+    },
+    Deleted {
+        deleted_count: u64,
+    },
+    // Internal errors only carry their code, like the error envelope in production.
+    Failed {
+        code: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+    Skipped,
+}
+
+impl WriteStatus {
+    pub fn failed(error: &Errors) -> Self {
+        Self::Failed {
+            code: error.code().to_string(),
+            message: error.public_message(),
+        }
+    }
+    pub fn is_success(&self) -> bool {
+        !matches!(self, Self::Failed { .. } | Self::Skipped)
+    }
+}
+
+fn id_to_string(id: &Bson) -> String {
+    match id {
+        Bson::String(id) => id.clone(),
+        Bson::ObjectId(id) => id.to_hex(),
+        id => id.to_string(),
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct BulkWriteReport {
+    pub mode: BulkWriteMode,
+    pub results: Vec<WriteStatus>,
+}
+
+impl BulkWriteReport {
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(WriteStatus::is_success)
+    }
+    pub fn failures(&self) -> impl Iterator<Item = (usize, &WriteStatus)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, status)| matches!(status, WriteStatus::Failed { .. }))
+    }
+    pub fn inserted_count(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|status| matches!(status, WriteStatus::Inserted { .. }))
+            .count()
+    }
+}
+
+impl From<UpdateResult> for WriteStatus {
+    fn from(result: UpdateResult) -> Self {
+        Self::Updated {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count,
+            upserted_id: result.upserted_id.as_ref().map(id_to_string),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::error_handler::HttpErrors;
+
+    #[test]
+    fn failures_carry_the_public_message_only() {
+        let status = WriteStatus::failed(&Errors::HttpError(HttpErrors::NotFound));
+        assert_eq!(
+            status,
+            WriteStatus::Failed {
+                code: "not_found".to_string(),
+                message: Some("The requested resource was not found".to_string()),
+            }
+        );
+        let status = WriteStatus::failed(&Errors::InternalError("secret detail".to_string()));
+        assert_eq!(
+            status,
+            WriteStatus::Failed {
+                code: "internal_error".to_string(),
+                message: None,
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            serde_json::json!({"status": "failed", "code": "internal_error"})
+        );
+    }
+}
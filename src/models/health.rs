@@ -0,0 +1,55 @@
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DependencyHealth {
+    pub name: String,
+    pub status: HealthStatus,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub build: BuildInfo,
+    pub uptime_seconds: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<DependencyHealth>,
+}
+
+impl HealthReport {
+    // Keeps only the status and latency of every dependency.
+    pub fn without_details(mut self) -> Self {
+        for check in &mut self.checks {
+            check.details = None;
+            check.error = None;
+        }
+        self
+    }
+}
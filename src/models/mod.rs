@@ -1 +1,8 @@
+pub mod auth;
+pub mod bulk;
+pub mod health;
+pub mod oauth;
+pub mod pagination;
+pub mod refresh_token;
+pub mod revoked_token;
 pub mod user;
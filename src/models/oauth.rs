@@ -0,0 +1,88 @@
+use crate::{database::query::ModelFields, helpers::enums::OAuthType, traits::model::ModelTrait};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Identity at an external provider linked to a user, keyed by the ID token `sub`.
+#[derive(Serialize, Deserialize, Clone, Debug, ModelFields)]
+pub struct ExternalIdentity {
+    pub provider: OAuthType,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+// Pending authorization request, consumed by the callback. Abandoned ones are
+// dropped by the TTL index on `expires_at`.
+#[derive(Serialize, Deserialize, Clone, Debug, ModelFields)]
+pub struct OAuthStateModel {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub state: String,
+    pub provider: OAuthType,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub expires_at: DateTime,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OAuthCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OAuthTokenResponse {
+    pub id_token: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub name: Option<String>,
+}
+
+impl ModelTrait for OAuthStateModel {
+    const COLLECTION_NAME: &'static str = "oauth_states";
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+    fn set_created_at(&mut self, created_at: u64) {
+        self.created_at = created_at;
+    }
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+    fn set_updated_at(&mut self, updated_at: u64) {
+        self.updated_at = updated_at;
+    }
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            IndexModel::builder()
+                .keys(doc! {"state": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            IndexModel::builder()
+                .keys(doc! {"expires_at": 1})
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        ]
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{
+    handlers::error_handler::{Errors, HttpErrors},
+    helpers::cursor::{decode_cursor, encode_cursor},
+};
+use mongodb::bson::{self, doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_SIZE: u64 = 10;
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+pub fn normalize_page(page: Option<u64>) -> u64 {
+    page.unwrap_or(1).max(1)
+}
+
+// Documents skipped before `page`. The page number comes straight from the query
+// string, so one whose offset does not fit the `$skip` stage is rejected.
+pub fn page_offset(page: u64, page_size: u64) -> Result<u64, Errors> {
+    page.saturating_sub(1)
+        .checked_mul(page_size)
+        .filter(|offset| *offset < i64::MAX as u64)
+        .ok_or_else(|| Errors::HttpError(HttpErrors::Message("Page is out of range".to_string())))
+}
+
+pub fn normalize_page_size(page_size: Option<u64>) -> u64 {
+    page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE)
+}
+
+// Parses `field,-other_field` into a `$sort` document, rejecting unknown fields.
+pub fn parse_sort(sort: &str, allowed_fields: &[&str]) -> Result<Document, Errors> {
+    let mut sort_document = Document::new();
+    for key in sort.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+        let (field, direction) = match key.strip_prefix('-') {
+            Some(field) => (field, -1),
+            None => (key.strip_prefix('+').unwrap_or(key), 1),
+        };
+        if !allowed_fields.contains(&field) {
+            return Err(Errors::HttpError(HttpErrors::Message(format!(
+                "Cannot sort by {}",
+                field
+            ))));
+        }
+        sort_document.insert(field, direction);
+    }
+    Ok(sort_document)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PaginationMetadata {
+    pub current_page: u64,
+    pub page_size: u64,
+    pub total_records: u64,
+    pub has_next_page: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Paginated<M> {
+    pub data: Vec<M>,
+    pub metadata: PaginationMetadata,
+}
+
+impl<M> Paginated<M> {
+    pub fn map<T>(self, f: impl FnMut(M) -> T) -> Paginated<T> {
+        Paginated {
+            data: self.data.into_iter().map(f).collect(),
+            metadata: self.metadata,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CursorPage<M> {
+    pub data: Vec<M>,
+    pub page_size: u64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+impl<M> CursorPage<M> {
+    pub fn map<T>(self, f: impl FnMut(M) -> T) -> CursorPage<T> {
+        CursorPage {
+            data: self.data.into_iter().map(f).collect(),
+            page_size: self.page_size,
+            next_cursor: self.next_cursor,
+            prev_cursor: self.prev_cursor,
+        }
+    }
+}
+
+// Keyset query for one page. `filter`, `sort` and `limit` are handed to the backend
+// as is; `into_page` trims the lookahead row and mints the cursors for the result.
+pub struct KeysetQuery {
+    pub filter: Document,
+    pub sort: Document,
+    pub limit: u64,
+    sort_field: String,
+    descending: bool,
+    backwards: bool,
+    has_cursor: bool,
+    page_size: u64,
+}
+
+impl KeysetQuery {
+    pub fn new(
+        filter: Option<Document>,
+        sort_field: impl Into<String>,
+        descending: bool,
+        cursor: Option<&str>,
+        page_size: Option<u64>,
+    ) -> Result<Self, Errors> {
+        let sort_field = sort_field.into();
+        let page_size = normalize_page_size(page_size);
+        let mut filter = filter.unwrap_or_default();
+        let mut backwards = false;
+        if let Some(cursor) = cursor {
+            let payload = decode_cursor(cursor)?;
+            let invalid = || Errors::HttpError(HttpErrors::Message("Invalid cursor".to_string()));
+            if payload.get_str("field").map_err(|_| invalid())? != sort_field
+                || payload.get_bool("descending").map_err(|_| invalid())? != descending
+            {
+                return Err(invalid());
+            }
+            backwards = payload.get_bool("backwards").map_err(|_| invalid())?;
+            let id = payload.get("id").ok_or_else(invalid)?.clone();
+            let value = payload.get("value").ok_or_else(invalid)?.clone();
+            let operator = if descending != backwards {
+                "$lt"
+            } else {
+                "$gt"
+            };
+            let position = if sort_field == "_id" {
+                doc! {"_id": {operator: id}}
+            } else {
+                doc! {"$or": [
+                    {&sort_field: {operator: value.clone()}},
+                    {&sort_field: value, "_id": {operator: id}},
+                ]}
+            };
+            filter = doc! {"$and": [filter, position]};
+        }
+        let direction = if descending != backwards { -1 } else { 1 };
+        let mut sort = doc! {&sort_field: direction};
+        sort.insert("_id", direction);
+        Ok(Self {
+            filter,
+            sort,
+            limit: page_size + 1,
+            sort_field,
+            descending,
+            backwards,
+            has_cursor: cursor.is_some(),
+            page_size,
+        })
+    }
+
+    fn cursor_for<M: Serialize>(&self, model: &M, backwards: bool) -> Result<String, Errors> {
+        let document =
+            bson::to_document(model).map_err(|error| Errors::InternalError(error.to_string()))?;
+        encode_cursor(&doc! {
+            "field": &self.sort_field,
+            "descending": self.descending,
+            "backwards": backwards,
+            "value": document.get(&self.sort_field).cloned().unwrap_or(Bson::Null),
+            "id": document.get("_id").cloned().unwrap_or(Bson::Null),
+        })
+    }
+
+    pub fn into_page<M: Serialize>(self, rows: Vec<M>) -> Result<CursorPage<M>, Errors> {
+        let mut rows = rows;
+        let has_more = rows.len() as u64 > self.page_size;
+        rows.truncate(self.page_size as usize);
+        if self.backwards {
+            rows.reverse();
+        }
+        let (has_next, has_prev) = if self.backwards {
+            (self.has_cursor, has_more)
+        } else {
+            (has_more, self.has_cursor)
+        };
+        let next_cursor = match rows.last() {
+            Some(last) if has_next => Some(self.cursor_for(last, false)?),
+            _ => None,
+        };
+        let prev_cursor = match rows.first() {
+            Some(first) if has_prev => Some(self.cursor_for(first, true)?),
+            _ => None,
+        };
+        Ok(CursorPage {
+            data: rows,
+            page_size: self.page_size,
+            next_cursor,
+            prev_cursor,
+        })
+    }
+}
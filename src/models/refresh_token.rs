@@ -0,0 +1,79 @@
+use crate::{database::query::ModelFields, traits::model::ModelTrait};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use validator::Validate;
+
+// One issued refresh token, keyed by its `jti`. Tokens of a login session share
+// a `family_id` so the whole chain can be revoked when a used token is replayed.
+// `expires_at` is a BSON date so the TTL index drops the record with the token.
+#[derive(Serialize, Deserialize, Clone, Debug, ModelFields)]
+pub struct RefreshTokenModel {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub user_id: String,
+    pub family_id: String,
+    pub expires_at: DateTime,
+    #[serde(default)]
+    pub used_at: Option<u64>,
+    #[serde(default)]
+    pub revoked: bool,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl RefreshTokenModel {
+    pub fn new(user_id: impl Into<String>, family_id: impl Into<String>, expires_at: u64) -> Self {
+        Self {
+            id: String::new(),
+            user_id: user_id.into(),
+            family_id: family_id.into(),
+            expires_at: DateTime::from_millis(expires_at as i64 * 1000),
+            used_at: None,
+            revoked: false,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct RefreshModel {
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub refresh_token: String,
+}
+
+impl ModelTrait for RefreshTokenModel {
+    const COLLECTION_NAME: &'static str = "refresh_tokens";
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+    fn set_created_at(&mut self, created_at: u64) {
+        self.created_at = created_at;
+    }
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+    fn set_updated_at(&mut self, updated_at: u64) {
+        self.updated_at = updated_at;
+    }
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            IndexModel::builder().keys(doc! {"family_id": 1}).build(),
+            IndexModel::builder().keys(doc! {"user_id": 1}).build(),
+            IndexModel::builder()
+                .keys(doc! {"expires_at": 1})
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        ]
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{database::query::ModelFields, traits::model::ModelTrait};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Denylisted access token. `expires_at` is a BSON date so the TTL index drops the
+// entry once the token would have expired anyway.
+#[derive(Serialize, Deserialize, Clone, Debug, ModelFields)]
+pub struct RevokedTokenModel {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub jti: String,
+    pub user_id: String,
+    pub expires_at: DateTime,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl RevokedTokenModel {
+    pub fn new(jti: impl Into<String>, user_id: impl Into<String>, expires_at: u64) -> Self {
+        Self {
+            id: String::new(),
+            jti: jti.into(),
+            user_id: user_id.into(),
+            expires_at: DateTime::from_millis(expires_at as i64 * 1000),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+}
+
+impl ModelTrait for RevokedTokenModel {
+    const COLLECTION_NAME: &'static str = "revoked_tokens";
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+    fn set_created_at(&mut self, created_at: u64) {
+        self.created_at = created_at;
+    }
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+    fn set_updated_at(&mut self, updated_at: u64) {
+        self.updated_at = updated_at;
+    }
+
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            IndexModel::builder()
+                .keys(doc! {"jti": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            IndexModel::builder()
+                .keys(doc! {"expires_at": 1})
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        ]
+    }
+}
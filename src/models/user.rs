@@ -1,7 +1,23 @@
-/*use crate::helpers::enums::*;
+use crate::{
+    database::query::{Filter, ModelFields, Update},
+    handlers::error_handler::{Errors, HttpErrors},
+    helpers::{
+        enums::{Permission, Role, UserStatus},
+        validators::validate_name,
+    },
+    models::{oauth::ExternalIdentity, pagination::parse_sort},
+    traits::model::ModelTrait,
+};
+use chrono::Utc;
+use mongodb::{
+    bson::{doc, Document},
+    options::IndexOptions,
+    IndexModel,
+};
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, ModelFields)]
 pub struct UserModel {
     #[serde(rename = "_id")]
     pub id: String,
@@ -10,40 +26,221 @@ pub struct UserModel {
     pub user_status: UserStatus,
     pub created_at: u64,
     pub updated_at: u64,
+    #[serde(default)]
     pub is_deleted: bool,
+    #[serde(default)]
+    pub deleted_at: Option<u64>,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    // Access tokens issued up to this time, in milliseconds, are rejected (logout
+    // everywhere). Set it with `tokens_cutoff`.
+    #[serde(default)]
+    pub tokens_valid_after: Option<u64>,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    #[serde(default)]
+    pub external_identities: Vec<ExternalIdentity>,
 }
-*/
 
-use crate::{helpers::enums::UserStatus, traits::model::ModelTrait};
-use serde::{Deserialize, Serialize};
+// Value for `tokens_valid_after` that rejects every token issued until now.
+pub fn tokens_cutoff() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
-pub struct UserModel {
-    #[serde(rename = "_id")]
+impl UserModel {
+    // Permissions granted directly plus those implied by the user's roles.
+    pub fn effective_permissions(&self) -> Vec<Permission> {
+        let mut permissions = self.permissions.clone();
+        for permission in self.roles.iter().flat_map(Role::permissions) {
+            if !permissions.contains(permission) {
+                permissions.push(*permission);
+            }
+        }
+        permissions
+    }
+}
+
+// Public view of a user, everything except the credentials.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserResponseModel {
     pub id: String,
     pub first_name: String,
     pub last_name: String,
+    pub email: String,
     pub user_status: UserStatus,
     pub created_at: u64,
     pub updated_at: u64,
     pub is_deleted: bool,
+    pub deleted_at: Option<u64>,
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+    pub external_identities: Vec<ExternalIdentity>,
 }
-#[derive(Serialize, Deserialize, Clone, Debug)]
+
+impl From<UserModel> for UserResponseModel {
+    fn from(user: UserModel) -> Self {
+        Self {
+            id: user.id,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            email: user.email,
+            user_status: user.user_status,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            is_deleted: user.is_deleted,
+            deleted_at: user.deleted_at,
+            roles: user.roles,
+            permissions: user.permissions,
+            external_identities: user.external_identities,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
 pub struct UserCreateModel {
+    #[validate(
+        length(min = 1, max = 100, message = "must be between 1 and 100 characters"),
+        custom(function = "validate_name")
+    )]
     pub first_name: String,
+    #[validate(
+        length(min = 1, max = 100, message = "must be between 1 and 100 characters"),
+        custom(function = "validate_name")
+    )]
     pub last_name: String,
 }
 
 impl UserCreateModel {
     pub fn get_user_model(&self) -> UserModel {
-        let mut user_model = UserModel::default();
-        user_model.first_name = self.first_name.clone();
-        user_model.last_name = self.last_name.clone();
-        user_model
+        UserModel {
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            roles: vec![Role::User],
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Validate)]
+pub struct UserUpdateModel {
+    #[validate(
+        length(min = 1, max = 100, message = "must be between 1 and 100 characters"),
+        custom(function = "validate_name")
+    )]
+    pub first_name: Option<String>,
+    #[validate(
+        length(min = 1, max = 100, message = "must be between 1 and 100 characters"),
+        custom(function = "validate_name")
+    )]
+    pub last_name: Option<String>,
+}
+
+impl UserUpdateModel {
+    pub fn get_update_document(&self) -> Result<Document, Errors> {
+        if self.first_name.is_none() && self.last_name.is_none() {
+            return Err(Errors::HttpError(HttpErrors::Message(
+                "Nothing to update".to_string(),
+            )));
+        }
+        let mut update = Update::new();
+        if let Some(first_name) = &self.first_name {
+            update = update.set(UserModel::FIRST_NAME, first_name.as_str());
+        }
+        if let Some(last_name) = &self.last_name {
+            update = update.set(UserModel::LAST_NAME, last_name.as_str());
+        }
+        update.build()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct UserStatusUpdateModel {
+    pub user_status: UserStatus,
+}
+
+impl UserStatusUpdateModel {
+    // Deactivating a user also invalidates the tokens they already hold.
+    pub fn get_update_document(&self) -> Result<Document, Errors> {
+        let mut update = Update::new().set(UserModel::USER_STATUS, self.user_status.clone());
+        if matches!(self.user_status, UserStatus::Inactive) {
+            update = update.set(UserModel::TOKENS_VALID_AFTER, tokens_cutoff());
+        }
+        update.build()
+    }
+}
+
+pub const USER_SORT_FIELDS: [&str; 5] = [
+    "first_name",
+    "last_name",
+    "user_status",
+    "created_at",
+    "updated_at",
+];
+
+// Soft deleted users are only listed for admins.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UserExportQuery {
+    #[serde(default)]
+    pub with_deleted: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UserListQuery {
+    pub page: Option<u64>,
+    pub page_size: Option<u64>,
+    pub sort: Option<String>,
+    pub user_status: Option<UserStatus>,
+    pub name: Option<String>,
+    pub cursor: Option<String>,
+}
+
+impl UserListQuery {
+    pub fn get_filter_document(&self) -> Result<Document, Errors> {
+        let mut filters = Vec::new();
+        if let Some(user_status) = &self.user_status {
+            filters.push(UserModel::USER_STATUS.eq(user_status.clone()));
+        }
+        if let Some(name) = self.name.as_deref().map(str::trim) {
+            if !name.is_empty() {
+                let pattern = regex::escape(name);
+                filters.push(Filter::or([
+                    UserModel::FIRST_NAME.regex(pattern.as_str(), true),
+                    UserModel::LAST_NAME.regex(pattern, true),
+                ]));
+            }
+        }
+        if filters.is_empty() {
+            return Ok(Document::new());
+        }
+        Filter::and(filters).build()
+    }
+    pub fn get_sort_document(&self) -> Result<Option<Document>, Errors> {
+        self.sort
+            .as_deref()
+            .map(|sort| parse_sort(sort, &USER_SORT_FIELDS))
+            .transpose()
+    }
+    // Keyset pagination orders by a single field, `_id` ascending unless `sort` says otherwise.
+    pub fn get_keyset_sort(&self) -> Result<(String, bool), Errors> {
+        let sort = self.get_sort_document()?.unwrap_or_default();
+        match sort.iter().next() {
+            Some((field, direction)) => Ok((field.clone(), direction.as_i32() == Some(-1))),
+            None => Ok(("_id".to_string(), false)),
+        }
     }
 }
 
 impl ModelTrait for UserModel {
+    const COLLECTION_NAME: &'static str = "users";
+    const SOFT_DELETE: bool = true;
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
     fn set_created_at(&mut self, created_at: u64) {
         self.created_at = created_at;
     }
@@ -53,4 +250,34 @@ impl ModelTrait for UserModel {
     fn set_updated_at(&mut self, updated_at: u64) {
         self.updated_at = updated_at;
     }
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            // Users created through OAuth may have no email, only real ones are unique.
+            IndexModel::builder()
+                .keys(doc! {"email": 1})
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! {"email": {"$gt": ""}})
+                        .build(),
+                )
+                .build(),
+            IndexModel::builder()
+                .keys(doc! {"user_status": 1, "created_at": -1})
+                .build(),
+            // An external account links to one user. Users without any are left out, as
+            // their empty arrays would all collide.
+            IndexModel::builder()
+                .keys(doc! {"external_identities.provider": 1, "external_identities.subject": 1})
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(
+                            doc! {"external_identities.subject": {"$exists": true}},
+                        )
+                        .build(),
+                )
+                .build(),
+        ]
+    }
 }
@@ -0,0 +1,119 @@
+use super::handle_json_response;
+use crate::{
+    database::mongodb::MongoClient,
+    helpers::cors::cors,
+    models::{
+        auth::{LoginModel, RegisterModel, TokenPairModel},
+        oauth::OAuthCallbackQuery,
+        refresh_token::RefreshModel,
+    },
+    services::{auth_service, oauth_service},
+    traits::{jwt::JwtToken, validated_json::ValidatedJson},
+};
+use actix_web::{get, http, post, web, HttpResponse, Responder, ResponseError};
+
+#[post("/register")]
+pub async fn register(
+    mongo_client: web::Data<MongoClient>,
+    input: ValidatedJson<RegisterModel>,
+) -> impl Responder {
+    let response = auth_service::register(mongo_client.get_ref(), input.into_inner()).await;
+    match response {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(error) => error.error_response(),
+    }
+}
+
+#[post("/login")]
+pub async fn login(
+    mongo_client: web::Data<MongoClient>,
+    input: ValidatedJson<LoginModel>,
+) -> impl Responder {
+    let response = auth_service::login(
+        mongo_client.get_ref(),
+        mongo_client.get_ref(),
+        input.into_inner(),
+    )
+    .await;
+    handle_json_response::<TokenPairModel>(response)
+}
+
+#[post("/refresh")]
+pub async fn refresh(
+    mongo_client: web::Data<MongoClient>,
+    input: ValidatedJson<RefreshModel>,
+) -> impl Responder {
+    let response = auth_service::refresh(
+        mongo_client.get_ref(),
+        mongo_client.get_ref(),
+        input.into_inner(),
+    )
+    .await;
+    handle_json_response::<TokenPairModel>(response)
+}
+
+#[post("/logout")]
+pub async fn logout(auth_token: JwtToken, mongo_client: web::Data<MongoClient>) -> impl Responder {
+    let response =
+        auth_service::logout(mongo_client.get_ref(), mongo_client.get_ref(), auth_token).await;
+    match response {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(error) => error.error_response(),
+    }
+}
+
+#[post("/logout-all")]
+pub async fn logout_all(
+    auth_token: JwtToken,
+    mongo_client: web::Data<MongoClient>,
+) -> impl Responder {
+    let response =
+        auth_service::logout_all(mongo_client.get_ref(), mongo_client.get_ref(), auth_token).await;
+    match response {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(error) => error.error_response(),
+    }
+}
+
+#[get("/oauth/{provider}/start")]
+pub async fn oauth_start(
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let response = oauth_service::start(mongo_client.get_ref(), &path).await;
+    match response {
+        Ok(url) => HttpResponse::Found()
+            .insert_header((http::header::LOCATION, url))
+            .finish(),
+        Err(error) => error.error_response(),
+    }
+}
+
+#[get("/oauth/{provider}/callback")]
+pub async fn oauth_callback(
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+    query: web::Query<OAuthCallbackQuery>,
+) -> impl Responder {
+    let response = oauth_service::callback(
+        mongo_client.get_ref(),
+        mongo_client.get_ref(),
+        mongo_client.get_ref(),
+        &path,
+        query.into_inner(),
+    )
+    .await;
+    handle_json_response::<TokenPairModel>(response)
+}
+
+pub fn routes() -> impl actix_web::dev::HttpServiceFactory {
+    web::scope("auth")
+        .wrap(cors(Some("auth")))
+        .service(register)
+        .service(login)
+        .service(refresh)
+        .service(logout)
+        .service(logout_all)
+        .service(oauth_start)
+        .service(oauth_callback)
+}
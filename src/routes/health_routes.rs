@@ -0,0 +1,105 @@
+use crate::{
+    config::AppConfig, helpers::cors::cors, models::health::HealthStatus,
+    services::health_service::HealthRegistry,
+};
+use actix_web::{get, web, HttpResponse, Responder};
+
+#[get("/live")]
+pub async fn live(registry: web::Data<HealthRegistry>) -> impl Responder {
+    health_check(registry).await
+}
+
+// `/health/live` under the `/health-check` paths that probes were configured with
+// before it existed.
+pub async fn health_check(registry: web::Data<HealthRegistry>) -> HttpResponse {
+    HttpResponse::Ok().json(registry.liveness())
+}
+
+// Public unless `health.expose_details` is set, so dependency versions and
+// errors are left out by default.
+#[get("/ready")]
+pub async fn ready(registry: web::Data<HealthRegistry>) -> impl Responder {
+    let mut report = registry.readiness().await;
+    if !AppConfig::get().health.expose_details {
+        report = report.without_details();
+    }
+    match report.status {
+        HealthStatus::Up => HttpResponse::Ok().json(report),
+        HealthStatus::Down => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
+
+pub fn routes() -> impl actix_web::dev::HttpServiceFactory {
+    web::scope("health")
+        .wrap(cors(Some("health")))
+        .service(live)
+        .service(ready)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::health::HealthCheck;
+    use actix_web::{test, App};
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+
+    struct Database {
+        up: bool,
+    }
+
+    #[async_trait]
+    impl HealthCheck for Database {
+        fn name(&self) -> &'static str {
+            "database"
+        }
+
+        async fn check(&self) -> Result<Value, String> {
+            if self.up {
+                Ok(json!({"server_version": "7.0.2"}))
+            } else {
+                Err("connection refused by 10.0.0.12:27017".to_string())
+            }
+        }
+    }
+
+    async fn get(up: bool, path: &str) -> (u16, Value) {
+        AppConfig::init_for_tests();
+        let registry = HealthRegistry::new().register(Database { up });
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .route("/health-check", web::get().to(health_check))
+                .service(routes()),
+        )
+        .await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(path).to_request()).await;
+        let status = response.status().as_u16();
+        (status, test::read_body_json(response).await)
+    }
+
+    #[actix_web::test]
+    async fn readiness_only_reports_status_and_latency() {
+        let (status, body) = get(true, "/health/ready").await;
+        assert_eq!(status, 200);
+        let check = &body["checks"][0];
+        assert_eq!(check["status"], "up");
+        assert!(check["latency_ms"].is_number());
+        assert!(check.get("details").is_none());
+
+        let (status, body) = get(false, "/health/ready").await;
+        assert_eq!(status, 503);
+        assert_eq!(body["status"], "down");
+        assert!(body["checks"][0].get("error").is_none());
+    }
+
+    #[actix_web::test]
+    async fn health_check_is_an_alias_of_liveness() {
+        let (status, alias) = get(false, "/health-check").await;
+        assert_eq!(status, 200);
+        let (_, liveness) = get(false, "/health/live").await;
+        assert_eq!(alias["status"], liveness["status"]);
+        assert_eq!(alias["build"], liveness["build"]);
+    }
+}
@@ -1,7 +1,23 @@
-use actix_web::web;
+use crate::handlers::error_handler::Errors;
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
+pub mod auth_routes;
+pub mod health_routes;
 pub mod user_routes;
 
 pub fn routes() -> impl actix_web::dev::HttpServiceFactory {
-    web::scope("api").service(user_routes::routes())
+    web::scope("api")
+        .service(auth_routes::routes())
+        .service(user_routes::routes())
+}
+
+fn handle_json_response<Model: Serialize + DeserializeOwned + Clone>(
+    response: Result<Model, Errors>,
+) -> impl Responder {
+    match response {
+        Ok(message) => HttpResponse::Ok().json(message),
+        Err(error) => error.error_response(),
+    }
 }
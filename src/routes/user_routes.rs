@@ -1,51 +1,193 @@
-use crate::handlers::error_handler::Errors;
-use crate::models::user::UserModel;
-use crate::traits::jwt::JwtToken;
+use super::handle_json_response;
+use crate::helpers::{
+    cors::cors,
+    enums::Role,
+    json_stream::{json_stream, StreamFormat},
+};
+use crate::models::pagination::{CursorPage, Paginated};
+use crate::models::user::{
+    UserExportQuery, UserListQuery, UserResponseModel, UserStatusUpdateModel, UserUpdateModel,
+};
+use crate::routes::health_routes::health_check;
+use crate::traits::current_user::CurrentUser;
+use crate::traits::rbac::{
+    Admin, ManageRoles, ManageUsers, ReadUsers, RequirePermission, RequireRole,
+};
+use crate::traits::validated_json::ValidatedJson;
 use crate::{
-    database::mongodb::MongoClient, models::user::UserCreateModel, services::user_service,
+    database::mongodb::MongoClient,
+    handlers::error_handler::{Errors, HttpErrors},
+    models::user::UserCreateModel,
+    services::user_service,
 };
-use actix_web::{get, post, web, HttpResponse, Responder, ResponseError};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-
-#[get("/health-check")]
-async fn health_check() -> impl Responder {
-    HttpResponse::Ok().body("Users Ok")
-}
+use actix_web::{
+    delete, get, patch, post, put, web, HttpRequest, HttpResponse, Responder, ResponseError,
+};
+use futures::TryStreamExt;
 
 #[post("/create")]
 pub async fn create_user(
     mongo_client: web::Data<MongoClient>,
-    input: web::Json<UserCreateModel>,
+    input: ValidatedJson<UserCreateModel>,
 ) -> impl Responder {
-    let response = user_service::create_user(mongo_client.get_ref().clone(), input.clone()).await;
+    let response = user_service::create_user(mongo_client.get_ref(), input.into_inner()).await;
     match response {
-        Ok(message) => HttpResponse::Created().json(message),
+        Ok(user) => HttpResponse::Created().json(UserResponseModel::from(user)),
         Err(error) => error.error_response(),
     }
 }
 
 #[get("/all")]
 pub async fn get_all_users(
-    auth_token: JwtToken,
+    auth_token: RequirePermission<ReadUsers>,
     mongo_client: web::Data<MongoClient>,
+    query: web::Query<UserExportQuery>,
+    request: HttpRequest,
 ) -> impl Responder {
-    let response = user_service::get_all_users(mongo_client.get_ref().clone()).await;
-    handle_json_response::<Vec<UserModel>>(response)
-}
-
-fn handle_json_response<Model: Serialize + DeserializeOwned + Clone>(
-    response: Result<Model, Errors>,
-) -> impl Responder {
+    if query.with_deleted && !auth_token.token.has_role(Role::Admin) {
+        return Errors::HttpError(HttpErrors::Forbidden).error_response();
+    }
+    let response = user_service::get_all_users(mongo_client.get_ref(), query.with_deleted).await;
     match response {
-        Ok(message) => HttpResponse::Created().json(message),
+        Ok(users) => json_stream(
+            users.map_ok(UserResponseModel::from),
+            StreamFormat::from_request(&request),
+        ),
         Err(error) => error.error_response(),
     }
 }
 
+#[get("")]
+pub async fn list_users(
+    _auth_token: RequirePermission<ReadUsers>,
+    mongo_client: web::Data<MongoClient>,
+    query: web::Query<UserListQuery>,
+) -> impl Responder {
+    let response = user_service::list_users(mongo_client.get_ref(), query.into_inner()).await;
+    handle_json_response::<Paginated<UserResponseModel>>(
+        response.map(|page| page.map(UserResponseModel::from)),
+    )
+}
+
+#[get("/cursor")]
+pub async fn list_users_by_cursor(
+    _auth_token: RequirePermission<ReadUsers>,
+    mongo_client: web::Data<MongoClient>,
+    query: web::Query<UserListQuery>,
+) -> impl Responder {
+    let response =
+        user_service::list_users_by_cursor(mongo_client.get_ref(), query.into_inner()).await;
+    handle_json_response::<CursorPage<UserResponseModel>>(
+        response.map(|page| page.map(UserResponseModel::from)),
+    )
+}
+
+#[get("/me")]
+pub async fn get_current_user(current_user: CurrentUser) -> impl Responder {
+    HttpResponse::Ok().json(UserResponseModel::from(current_user.user))
+}
+
+#[get("/{id}")]
+pub async fn get_user(
+    _auth_token: RequirePermission<ReadUsers>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let response = user_service::get_user(mongo_client.get_ref(), &path).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
+#[patch("/{id}")]
+pub async fn update_user(
+    _auth_token: RequirePermission<ManageUsers>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+    input: ValidatedJson<UserUpdateModel>,
+) -> impl Responder {
+    let response =
+        user_service::update_user(mongo_client.get_ref(), &path, input.into_inner()).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
+#[put("/{id}/status")]
+pub async fn update_user_status(
+    _auth_token: RequirePermission<ManageUsers>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+    input: ValidatedJson<UserStatusUpdateModel>,
+) -> impl Responder {
+    let response =
+        user_service::update_user_status(mongo_client.get_ref(), &path, input.into_inner()).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
+#[delete("/{id}")]
+pub async fn delete_user(
+    _auth_token: RequirePermission<ManageUsers>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let response = user_service::delete_user(mongo_client.get_ref(), &path).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
+#[post("/{id}/restore")]
+pub async fn restore_user(
+    _auth_token: RequireRole<Admin>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let response = user_service::restore_user(mongo_client.get_ref(), &path).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
+#[delete("/{id}/purge")]
+pub async fn purge_user(
+    _auth_token: RequireRole<Admin>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let response = user_service::purge_user(mongo_client.get_ref(), &path).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
+#[put("/{id}/roles/{role}")]
+pub async fn grant_role(
+    _auth_token: RequirePermission<ManageRoles>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<(String, Role)>,
+) -> impl Responder {
+    let (id, role) = path.into_inner();
+    let response = user_service::grant_role(mongo_client.get_ref(), &id, role).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
+#[delete("/{id}/roles/{role}")]
+pub async fn revoke_role(
+    _auth_token: RequirePermission<ManageRoles>,
+    mongo_client: web::Data<MongoClient>,
+    path: web::Path<(String, Role)>,
+) -> impl Responder {
+    let (id, role) = path.into_inner();
+    let response = user_service::revoke_role(mongo_client.get_ref(), &id, role).await;
+    handle_json_response::<UserResponseModel>(response.map(UserResponseModel::from))
+}
+
 pub fn routes() -> impl actix_web::dev::HttpServiceFactory {
     web::scope("users")
+        .wrap(cors(Some("users")))
+        .route("/health-check", web::get().to(health_check))
         .service(create_user)
         .service(get_all_users)
-        .service(health_check)
+        .service(list_users)
+        .service(list_users_by_cursor)
+        .service(get_current_user)
+        .service(get_user)
+        .service(update_user)
+        .service(update_user_status)
+        .service(delete_user)
+        .service(restore_user)
+        .service(purge_user)
+        .service(grant_role)
+        .service(revoke_role)
 }
@@ -0,0 +1,515 @@
+use crate::{
+    config::AppConfig,
+    database::query::{Filter, Update},
+    handlers::error_handler::{Errors, HttpErrors},
+    helpers::{
+        enums::{JwtTokenType, Role, UserStatus},
+        password::{hash_password, verify_password},
+    },
+    models::{
+        auth::{normalize_email, LoginModel, RegisterModel, TokenPairModel},
+        refresh_token::{RefreshModel, RefreshTokenModel},
+        revoked_token::RevokedTokenModel,
+        user::{tokens_cutoff, UserModel},
+    },
+    traits::{jwt::JwtToken, repository::Repository},
+};
+use chrono::Utc;
+use log::warn;
+use mongodb::bson::oid::ObjectId;
+
+async fn find_by_email(
+    repository: &impl Repository<UserModel>,
+    email: &str,
+) -> Result<Option<UserModel>, Errors> {
+    Ok(repository
+        .find(Some(UserModel::EMAIL.eq(email).build()?))
+        .await?
+        .into_iter()
+        .next())
+}
+
+fn unauthorized() -> Errors {
+    Errors::HttpError(HttpErrors::Unauthorized)
+}
+
+// Issues an access/refresh pair and records the refresh token so it can only be
+// redeemed once. A missing `family_id` starts a new login session.
+pub async fn issue_tokens(
+    refresh_tokens: &impl Repository<RefreshTokenModel>,
+    user: &UserModel,
+    family_id: Option<String>,
+) -> Result<TokenPairModel, Errors> {
+    let user_id = user.id.as_str();
+    let family_id = family_id.unwrap_or_else(|| ObjectId::new().to_hex());
+    let mut refresh_claims =
+        JwtToken::new(user_id, JwtTokenType::Refresh, String::new(), &family_id);
+    let record = refresh_tokens
+        .create(RefreshTokenModel::new(
+            user_id,
+            &family_id,
+            refresh_claims.exp,
+        ))
+        .await?;
+    refresh_claims.jti = record.id;
+    let mut access_claims = JwtToken::new(
+        user_id,
+        JwtTokenType::Access,
+        ObjectId::new().to_hex(),
+        family_id,
+    );
+    access_claims.roles = user.roles.clone();
+    access_claims.permissions = user.effective_permissions();
+    // A cutoff set in this same millisecond must not reject the new token.
+    if let Some(valid_after) = user.tokens_valid_after {
+        access_claims.iat_ms = access_claims.iat_ms.max(valid_after + 1);
+    }
+    Ok(TokenPairModel::bearer(
+        access_claims.encode_self()?,
+        refresh_claims.encode_self()?,
+    ))
+}
+
+async fn revoke_refresh_tokens(
+    refresh_tokens: &impl Repository<RefreshTokenModel>,
+    filter: Filter<RefreshTokenModel>,
+) -> Result<(), Errors> {
+    let active_tokens = refresh_tokens
+        .find(Some(
+            Filter::and([filter, RefreshTokenModel::REVOKED.eq(false)]).build()?,
+        ))
+        .await?;
+    let revoke = Update::new()
+        .set(RefreshTokenModel::REVOKED, true)
+        .build()?;
+    for token in active_tokens {
+        refresh_tokens.update(&token.id, revoke.clone()).await?;
+    }
+    Ok(())
+}
+
+// Succeeds the same way whether or not the email is taken, so the endpoint cannot
+// be used to enumerate users. The password is hashed either way to keep the timing alike.
+pub async fn register(
+    repository: &impl Repository<UserModel>,
+    input: RegisterModel,
+) -> Result<(), Errors> {
+    let email = normalize_email(&input.email);
+    let password_hash = hash_password(input.password).await?;
+    if find_by_email(repository, &email).await?.is_some() {
+        return Ok(());
+    }
+    let bootstrap_admin_email = &AppConfig::get().auth.bootstrap_admin_email;
+    let roles =
+        if !bootstrap_admin_email.is_empty() && email == normalize_email(bootstrap_admin_email) {
+            vec![Role::User, Role::Admin]
+        } else {
+            vec![Role::User]
+        };
+    let user = UserModel {
+        first_name: input.first_name,
+        last_name: input.last_name,
+        email,
+        roles,
+        password_hash: Some(password_hash),
+        ..Default::default()
+    };
+    match repository.create(user).await {
+        // A concurrent registration of the same email won the unique index.
+        Err(error) if error.code() == "duplicate_key" => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+// Unknown emails, wrong passwords and inactive accounts all yield the same
+// `Unauthorized` so the endpoint cannot be used to enumerate users.
+pub async fn login(
+    repository: &impl Repository<UserModel>,
+    refresh_tokens: &impl Repository<RefreshTokenModel>,
+    input: LoginModel,
+) -> Result<TokenPairModel, Errors> {
+    let user = find_by_email(repository, &normalize_email(&input.email)).await?;
+    let password_hash = user.as_ref().and_then(|user| user.password_hash.clone());
+    let is_valid = verify_password(input.password, password_hash).await;
+    match user {
+        Some(user) if is_valid && matches!(user.user_status, UserStatus::Active) => {
+            issue_tokens(refresh_tokens, &user, None).await
+        }
+        _ => Err(unauthorized()),
+    }
+}
+
+// Refresh tokens are single use. Presenting one that was already rotated means it
+// leaked, so every token of its family is revoked and the caller has to log in again.
+pub async fn refresh(
+    repository: &impl Repository<UserModel>,
+    refresh_tokens: &impl Repository<RefreshTokenModel>,
+    input: RefreshModel,
+) -> Result<TokenPairModel, Errors> {
+    let claims = JwtToken::decode(input.refresh_token).map_err(|_| unauthorized())?;
+    if claims.token_type != JwtTokenType::Refresh {
+        return Err(unauthorized());
+    }
+    let record = refresh_tokens
+        .get_by_id(&claims.jti)
+        .await?
+        .ok_or_else(unauthorized)?;
+    if record.revoked || record.user_id != claims.user_id() || record.family_id != claims.family_id
+    {
+        return Err(unauthorized());
+    }
+    let current_timestamp = Utc::now().timestamp() as u64;
+    let unused = Filter::and([
+        RefreshTokenModel::ID.eq(record.id.as_str()),
+        RefreshTokenModel::USED_AT.eq(None::<u64>),
+        RefreshTokenModel::REVOKED.eq(false),
+    ]);
+    let rotated = refresh_tokens
+        .update_where(
+            unused.build()?,
+            Update::new()
+                .set(RefreshTokenModel::USED_AT, current_timestamp)
+                .build()?,
+        )
+        .await?;
+    if rotated.is_none() {
+        warn!(
+            "Refresh token reuse detected for user {}, revoking family {}",
+            record.user_id, record.family_id
+        );
+        revoke_refresh_tokens(
+            refresh_tokens,
+            RefreshTokenModel::FAMILY_ID.eq(record.family_id.as_str()),
+        )
+        .await?;
+        return Err(unauthorized());
+    }
+    let user = repository
+        .get_by_id(&record.user_id)
+        .await?
+        .filter(|user| matches!(user.user_status, UserStatus::Active))
+        .ok_or_else(unauthorized)?;
+    issue_tokens(refresh_tokens, &user, Some(record.family_id)).await
+}
+
+// Called by the `JwtToken` extractor for every authenticated request. Returns the
+// token's user so the extractor can cache it for `CurrentUser`. `iat` only has
+// second precision, so tokens issued in the second of a cutoff are rejected as well.
+pub async fn ensure_token_active(
+    repository: &impl Repository<UserModel>,
+    revoked_tokens: &impl Repository<RevokedTokenModel>,
+    claims: &JwtToken,
+) -> Result<UserModel, Errors> {
+    if revoked_tokens
+        .count(Some(
+            RevokedTokenModel::JTI.eq(claims.jti.as_str()).build()?,
+        ))
+        .await?
+        > 0
+    {
+        return Err(unauthorized());
+    }
+    let user = repository
+        .get_by_id(claims.user_id())
+        .await?
+        .filter(|user| matches!(user.user_status, UserStatus::Active))
+        .ok_or_else(unauthorized)?;
+    match user.tokens_valid_after {
+        Some(valid_after) if claims.issued_at_millis() <= valid_after => Err(unauthorized()),
+        _ => Ok(user),
+    }
+}
+
+pub async fn logout(
+    refresh_tokens: &impl Repository<RefreshTokenModel>,
+    revoked_tokens: &impl Repository<RevokedTokenModel>,
+    claims: JwtToken,
+) -> Result<(), Errors> {
+    revoked_tokens
+        .create(RevokedTokenModel::new(
+            &claims.jti,
+            claims.user_id(),
+            claims.exp,
+        ))
+        .await?;
+    revoke_refresh_tokens(
+        refresh_tokens,
+        RefreshTokenModel::FAMILY_ID.eq(claims.family_id.as_str()),
+    )
+    .await
+}
+
+pub async fn logout_all(
+    repository: &impl Repository<UserModel>,
+    refresh_tokens: &impl Repository<RefreshTokenModel>,
+    claims: JwtToken,
+) -> Result<(), Errors> {
+    repository
+        .update(
+            claims.user_id(),
+            Update::new()
+                .set(UserModel::TOKENS_VALID_AFTER, tokens_cutoff())
+                .build()?,
+        )
+        .await?
+        .ok_or_else(unauthorized)?;
+    revoke_refresh_tokens(
+        refresh_tokens,
+        RefreshTokenModel::USER_ID.eq(claims.user_id()),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::memory::InMemoryRepository, helpers::jwt_keys::JwtKeys};
+
+    struct Repositories {
+        users: InMemoryRepository<UserModel>,
+        refresh_tokens: InMemoryRepository<RefreshTokenModel>,
+        revoked_tokens: InMemoryRepository<RevokedTokenModel>,
+    }
+
+    fn repositories() -> Repositories {
+        JwtKeys::init_for_tests();
+        Repositories {
+            users: InMemoryRepository::new(),
+            refresh_tokens: InMemoryRepository::new(),
+            revoked_tokens: InMemoryRepository::new(),
+        }
+    }
+
+    fn registration(email: &str) -> RegisterModel {
+        RegisterModel {
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+            email: email.to_string(),
+            password: "correct horse".to_string(),
+        }
+    }
+
+    fn credentials(email: &str, password: &str) -> LoginModel {
+        LoginModel {
+            email: email.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    fn is_unauthorized<T>(result: Result<T, Errors>) -> bool {
+        matches!(result, Err(Errors::HttpError(HttpErrors::Unauthorized)))
+    }
+
+    async fn registered(repositories: &Repositories, email: &str) -> UserModel {
+        register(&repositories.users, registration(email))
+            .await
+            .unwrap();
+        find_by_email(&repositories.users, &normalize_email(email))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn registers_users_once_per_email() {
+        let repositories = repositories();
+        let user = registered(&repositories, "Ada@Example.com ").await;
+        assert_eq!(user.email, "ada@example.com");
+        assert_eq!(user.roles, vec![Role::User]);
+        assert!(user.password_hash.is_some());
+
+        // Registering a taken email looks like success but changes nothing.
+        let mut again = registration("ada@example.com");
+        again.password = "another password".to_string();
+        register(&repositories.users, again).await.unwrap();
+        let users = repositories.users.find(None).await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].password_hash, user.password_hash);
+
+        let admin = registered(&repositories, "admin@example.com").await;
+        assert_eq!(admin.roles, vec![Role::User, Role::Admin]);
+    }
+
+    #[actix_web::test]
+    async fn logs_in_active_users_with_the_right_password() {
+        let repositories = repositories();
+        let user = registered(&repositories, "ada@example.com").await;
+        let tokens = login(
+            &repositories.users,
+            &repositories.refresh_tokens,
+            credentials("ADA@example.com", "correct horse"),
+        )
+        .await
+        .unwrap();
+        let claims = JwtToken::decode(tokens.access_token).unwrap();
+        assert_eq!(claims.user_id(), user.id);
+        assert_eq!(claims.token_type, JwtTokenType::Access);
+
+        assert!(is_unauthorized(
+            login(
+                &repositories.users,
+                &repositories.refresh_tokens,
+                credentials("ada@example.com", "wrong horse"),
+            )
+            .await
+        ));
+        assert!(is_unauthorized(
+            login(
+                &repositories.users,
+                &repositories.refresh_tokens,
+                credentials("grace@example.com", "correct horse"),
+            )
+            .await
+        ));
+
+        repositories
+            .users
+            .update(
+                &user.id,
+                Update::new()
+                    .set(UserModel::USER_STATUS, UserStatus::Inactive)
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(is_unauthorized(
+            login(
+                &repositories.users,
+                &repositories.refresh_tokens,
+                credentials("ada@example.com", "correct horse"),
+            )
+            .await
+        ));
+    }
+
+    #[actix_web::test]
+    async fn rotates_refresh_tokens_and_revokes_reused_families() {
+        let repositories = repositories();
+        let user = registered(&repositories, "ada@example.com").await;
+        let first = issue_tokens(&repositories.refresh_tokens, &user, None)
+            .await
+            .unwrap();
+        let refresh_with = |token: &str| {
+            refresh(
+                &repositories.users,
+                &repositories.refresh_tokens,
+                RefreshModel {
+                    refresh_token: token.to_string(),
+                },
+            )
+        };
+        let second = refresh_with(&first.refresh_token).await.unwrap();
+        assert!(is_unauthorized(refresh_with(&first.access_token).await));
+
+        // Replaying the rotated token revokes the token issued in its place.
+        assert!(is_unauthorized(refresh_with(&first.refresh_token).await));
+        assert!(is_unauthorized(refresh_with(&second.refresh_token).await));
+        let family = repositories.refresh_tokens.find(None).await.unwrap();
+        assert_eq!(family.len(), 2);
+        assert!(family.iter().all(|token| token.revoked));
+    }
+
+    #[actix_web::test]
+    async fn logout_revokes_the_access_token_and_its_family() {
+        let repositories = repositories();
+        let user = registered(&repositories, "ada@example.com").await;
+        let tokens = issue_tokens(&repositories.refresh_tokens, &user, None)
+            .await
+            .unwrap();
+        let claims = JwtToken::decode(tokens.access_token).unwrap();
+        ensure_token_active(&repositories.users, &repositories.revoked_tokens, &claims)
+            .await
+            .unwrap();
+
+        logout(
+            &repositories.refresh_tokens,
+            &repositories.revoked_tokens,
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(is_unauthorized(
+            ensure_token_active(&repositories.users, &repositories.revoked_tokens, &claims).await
+        ));
+        assert!(is_unauthorized(
+            refresh(
+                &repositories.users,
+                &repositories.refresh_tokens,
+                RefreshModel {
+                    refresh_token: tokens.refresh_token,
+                },
+            )
+            .await
+        ));
+    }
+
+    #[actix_web::test]
+    async fn rejects_tokens_issued_before_a_cutoff_or_for_inactive_users() {
+        let repositories = repositories();
+        let user = registered(&repositories, "ada@example.com").await;
+        let tokens = issue_tokens(&repositories.refresh_tokens, &user, None)
+            .await
+            .unwrap();
+        let claims = JwtToken::decode(tokens.access_token).unwrap();
+
+        logout_all(
+            &repositories.users,
+            &repositories.refresh_tokens,
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(is_unauthorized(
+            ensure_token_active(&repositories.users, &repositories.revoked_tokens, &claims).await
+        ));
+
+        // Issued in the same second as the cutoff but after it.
+        let cutoff = repositories
+            .users
+            .get_by_id(&user.id)
+            .await
+            .unwrap()
+            .and_then(|user| user.tokens_valid_after)
+            .unwrap();
+        let mut fresh = claims.clone();
+        fresh.iat = cutoff / 1000;
+        fresh.iat_ms = cutoff + 1;
+        ensure_token_active(&repositories.users, &repositories.revoked_tokens, &fresh)
+            .await
+            .unwrap();
+        let mut stale = fresh.clone();
+        stale.iat_ms = cutoff;
+        assert!(is_unauthorized(
+            ensure_token_active(&repositories.users, &repositories.revoked_tokens, &stale).await
+        ));
+
+        // Logging in again right away gets a usable token.
+        let tokens = login(
+            &repositories.users,
+            &repositories.refresh_tokens,
+            credentials("ada@example.com", "correct horse"),
+        )
+        .await
+        .unwrap();
+        let again = JwtToken::decode(tokens.access_token).unwrap();
+        ensure_token_active(&repositories.users, &repositories.revoked_tokens, &again)
+            .await
+            .unwrap();
+
+        repositories
+            .users
+            .update(
+                &user.id,
+                Update::new()
+                    .set(UserModel::USER_STATUS, UserStatus::Inactive)
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(is_unauthorized(
+            ensure_token_active(&repositories.users, &repositories.revoked_tokens, &fresh).await
+        ));
+    }
+}
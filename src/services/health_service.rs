@@ -0,0 +1,89 @@
+use crate::{
+    models::health::{BuildInfo, DependencyHealth, HealthReport, HealthStatus},
+    traits::health::HealthCheck,
+};
+use actix_web::rt::time::timeout;
+use futures::future::join_all;
+use log::warn;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone)]
+pub struct HealthRegistry {
+    started_at: Instant,
+    checks: Vec<Arc<dyn HealthCheck>>,
+}
+
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            checks: Vec::new(),
+        }
+    }
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, check: impl HealthCheck + 'static) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+
+    fn report(&self, checks: Vec<DependencyHealth>) -> HealthReport {
+        let status = if checks.iter().all(|check| check.status == HealthStatus::Up) {
+            HealthStatus::Up
+        } else {
+            HealthStatus::Down
+        };
+        HealthReport {
+            status,
+            build: BuildInfo::current(),
+            uptime_seconds: self.started_at.elapsed().as_secs(),
+            checks,
+        }
+    }
+
+    // Liveness only says the process is serving requests, so dependencies are not checked.
+    pub fn liveness(&self) -> HealthReport {
+        self.report(Vec::new())
+    }
+
+    pub async fn readiness(&self) -> HealthReport {
+        let checks = join_all(self.checks.iter().map(|check| async move {
+            let started_at = Instant::now();
+            let result = timeout(CHECK_TIMEOUT, check.check())
+                .await
+                .unwrap_or_else(|_| Err("Health check timed out".to_string()));
+            let latency_ms = started_at.elapsed().as_secs_f64() * 1000.0;
+            match result {
+                Ok(details) => DependencyHealth {
+                    name: check.name().to_string(),
+                    status: HealthStatus::Up,
+                    latency_ms,
+                    details: Some(details),
+                    error: None,
+                },
+                Err(error) => {
+                    warn!("Health check {} failed: {}", check.name(), error);
+                    DependencyHealth {
+                        name: check.name().to_string(),
+                        status: HealthStatus::Down,
+                        latency_ms,
+                        details: None,
+                        error: Some(error),
+                    }
+                }
+            }
+        }))
+        .await;
+        self.report(checks)
+    }
+}
@@ -1 +1,4 @@
+pub mod auth_service;
+pub mod health_service;
+pub mod oauth_service;
 pub mod user_service;
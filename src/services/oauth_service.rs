@@ -0,0 +1,524 @@
+use crate::{
+    config::{AppConfig, OAuthProviderConfig},
+    database::query::{Filter, Update},
+    handlers::error_handler::{Errors, HttpErrors},
+    helpers::enums::{OAuthType, Role, UserStatus},
+    models::{
+        auth::{normalize_email, TokenPairModel},
+        oauth::{
+            ExternalIdentity, IdTokenClaims, OAuthCallbackQuery, OAuthStateModel,
+            OAuthTokenResponse,
+        },
+        refresh_token::RefreshTokenModel,
+        user::UserModel,
+    },
+    services::auth_service::issue_tokens,
+    traits::repository::Repository,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use lazy_static::lazy_static;
+use mongodb::bson::DateTime;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+
+const STATE_TTL_SECONDS: i64 = 10 * 60;
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+lazy_static! {
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+    // Provider keys by `jwks_uri`.
+    static ref JWKS_CACHE: RwLock<HashMap<String, CachedJwks>> = RwLock::new(HashMap::new());
+}
+
+fn unauthorized() -> Errors {
+    Errors::HttpError(HttpErrors::Unauthorized)
+}
+
+fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+pub fn provider_config(provider: &str) -> Result<(OAuthType, OAuthProviderConfig), Errors> {
+    let provider = OAuthType::from_path(provider).ok_or(Errors::HttpError(HttpErrors::NotFound))?;
+    AppConfig::get()
+        .oauth
+        .provider(provider)
+        .map(|config| (provider, config.clone()))
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+// Records the PKCE verifier and nonce for this attempt and returns the provider
+// authorization URL the client is redirected to.
+pub async fn start(
+    oauth_states: &impl Repository<OAuthStateModel>,
+    provider: &str,
+) -> Result<String, Errors> {
+    let (provider, config) = provider_config(provider)?;
+    let state = oauth_states
+        .create(OAuthStateModel {
+            id: String::new(),
+            state: random_string(32),
+            provider,
+            code_verifier: random_string(64),
+            nonce: random_string(32),
+            expires_at: DateTime::from_millis((Utc::now().timestamp() + STATE_TTL_SECONDS) * 1000),
+            created_at: 0,
+            updated_at: 0,
+        })
+        .await?;
+    let mut url = reqwest::Url::parse(&config.authorization_endpoint)
+        .map_err(|error| Errors::InternalError(error.to_string()))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_uri)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state.state)
+        .append_pair("nonce", &state.nonce)
+        .append_pair("code_challenge", &pkce_challenge(&state.code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    Ok(url.to_string())
+}
+
+async fn take_state(
+    oauth_states: &impl Repository<OAuthStateModel>,
+    provider: OAuthType,
+    state: &str,
+) -> Result<OAuthStateModel, Errors> {
+    let invalid_state =
+        || Errors::HttpError(HttpErrors::Message("Invalid OAuth state".to_string()));
+    // Taking the state out in one step makes it single use, even for concurrent
+    // callbacks and when the rest of the flow fails.
+    let record = oauth_states
+        .purge_where(OAuthStateModel::STATE.eq(state).build()?)
+        .await?
+        .ok_or_else(invalid_state)?;
+    if record.provider != provider || record.expires_at < DateTime::now() {
+        return Err(invalid_state());
+    }
+    Ok(record)
+}
+
+async fn exchange_code(
+    config: &OAuthProviderConfig,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, Errors> {
+    let response = HTTP_CLIENT
+        .post(&config.token_endpoint)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("client_secret", config.client_secret.as_str()),
+            ("code_verifier", code_verifier),
+        ])
+        .send()
+        .await
+        .map_err(|error| Errors::InternalError(error.to_string()))?;
+    if !response.status().is_success() {
+        return Err(unauthorized());
+    }
+    response
+        .json::<OAuthTokenResponse>()
+        .await
+        .map_err(|error| Errors::InternalError(error.to_string()))?
+        .id_token
+        .ok_or_else(unauthorized)
+}
+
+fn find_jwk(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None => keys.keys.first(),
+    }
+    .cloned()
+}
+
+// The keys are fetched again only when the token names a `kid` missing from the
+// cached set, which is how providers rotate keys, and at most once per
+// `JWKS_REFRESH_INTERVAL` so made-up ids cannot turn every login into a fetch.
+async fn provider_jwk(config: &OAuthProviderConfig, kid: Option<&str>) -> Result<Jwk, Errors> {
+    let cached = JWKS_CACHE
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&config.jwks_uri)
+        .map(|cached| {
+            let fresh = cached.fetched_at.elapsed() < JWKS_REFRESH_INTERVAL;
+            (find_jwk(&cached.keys, kid), fresh)
+        });
+    match cached {
+        Some((Some(jwk), _)) => return Ok(jwk),
+        Some((None, true)) => return Err(unauthorized()),
+        _ => {}
+    }
+    let keys = HTTP_CLIENT
+        .get(&config.jwks_uri)
+        .send()
+        .await
+        .map_err(|error| Errors::InternalError(error.to_string()))?
+        .json::<JwkSet>()
+        .await
+        .map_err(|error| Errors::InternalError(error.to_string()))?;
+    let jwk = find_jwk(&keys, kid);
+    JWKS_CACHE
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(
+            config.jwks_uri.clone(),
+            CachedJwks {
+                keys,
+                fetched_at: Instant::now(),
+            },
+        );
+    jwk.ok_or_else(unauthorized)
+}
+
+async fn verify_id_token(
+    config: &OAuthProviderConfig,
+    id_token: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, Errors> {
+    let header = decode_header(id_token).map_err(|_| unauthorized())?;
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(unauthorized());
+    }
+    let jwk = provider_jwk(config, header.kid.as_deref()).await?;
+    let decoding_key = DecodingKey::from_jwk(&jwk).map_err(|_| unauthorized())?;
+    let mut validation = Validation::new(header.alg);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    validation.set_issuer(&[config.issuer.as_str()]);
+    validation.set_audience(&[config.client_id.as_str()]);
+    let claims = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+        .map_err(|_| unauthorized())?
+        .claims;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(unauthorized());
+    }
+    Ok(claims)
+}
+
+// Finds the user linked to this identity. Otherwise a verified email links the
+// identity to the existing account when `link_verified_email` allows it, and
+// failing that a new user is created.
+async fn link_user(
+    repository: &impl Repository<UserModel>,
+    provider: OAuthType,
+    link_verified_email: bool,
+    claims: IdTokenClaims,
+) -> Result<UserModel, Errors> {
+    let identity_filter = UserModel::EXTERNAL_IDENTITIES.elem_match(Filter::and([
+        ExternalIdentity::PROVIDER.eq(provider),
+        ExternalIdentity::SUBJECT.eq(claims.sub.as_str()),
+    ]));
+    let linked = repository
+        .find(Some(identity_filter.build()?))
+        .await?
+        .into_iter()
+        .next();
+    if let Some(user) = linked {
+        return Ok(user);
+    }
+    let email = claims.email.as_deref().map(normalize_email);
+    let identity = ExternalIdentity {
+        provider,
+        subject: claims.sub.clone(),
+        email: email.clone(),
+    };
+    let existing = match &email {
+        Some(email) if claims.email_verified => repository
+            .find(Some(UserModel::EMAIL.eq(email.as_str()).build()?))
+            .await?
+            .into_iter()
+            .next(),
+        _ => None,
+    };
+    match existing {
+        Some(_) if !link_verified_email => Err(Errors::HttpError(HttpErrors::Message(
+            "An account with this email already exists".to_string(),
+        ))),
+        Some(user) => {
+            let update = Update::new().push(UserModel::EXTERNAL_IDENTITIES, identity);
+            repository
+                .update(&user.id, update.build()?)
+                .await?
+                .ok_or_else(unauthorized)
+        }
+        None => {
+            let user = UserModel {
+                first_name: claims.given_name.or(claims.name).unwrap_or_default(),
+                last_name: claims.family_name.unwrap_or_default(),
+                email: if claims.email_verified {
+                    email.unwrap_or_default()
+                } else {
+                    String::new()
+                },
+                roles: vec![Role::User],
+                external_identities: vec![identity],
+                ..Default::default()
+            };
+            repository.create(user).await
+        }
+    }
+}
+
+pub async fn callback(
+    repository: &impl Repository<UserModel>,
+    refresh_tokens: &impl Repository<RefreshTokenModel>,
+    oauth_states: &impl Repository<OAuthStateModel>,
+    provider: &str,
+    query: OAuthCallbackQuery,
+) -> Result<TokenPairModel, Errors> {
+    let (provider, config) = provider_config(provider)?;
+    if let Some(error) = query.error {
+        return Err(Errors::HttpError(HttpErrors::Message(error)));
+    }
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(Errors::HttpError(HttpErrors::BadRequest));
+    };
+    let state = take_state(oauth_states, provider, &state).await?;
+    let id_token = exchange_code(&config, &code, &state.code_verifier).await?;
+    let claims = verify_id_token(&config, &id_token, &state.nonce).await?;
+    let user = link_user(repository, provider, config.link_verified_email, claims).await?;
+    if !matches!(user.user_status, UserStatus::Active) {
+        return Err(unauthorized());
+    }
+    issue_tokens(refresh_tokens, &user, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::memory::InMemoryRepository;
+
+    async fn pending(oauth_states: &InMemoryRepository<OAuthStateModel>, state: &str) {
+        oauth_states
+            .create(OAuthStateModel {
+                id: String::new(),
+                state: state.to_string(),
+                provider: OAuthType::Google,
+                code_verifier: random_string(64),
+                nonce: random_string(32),
+                expires_at: DateTime::from_millis(
+                    (Utc::now().timestamp() + STATE_TTL_SECONDS) * 1000,
+                ),
+                created_at: 0,
+                updated_at: 0,
+            })
+            .await
+            .unwrap();
+    }
+
+    fn claims(sub: &str, email: &str, email_verified: bool) -> IdTokenClaims {
+        IdTokenClaims {
+            sub: sub.to_string(),
+            nonce: None,
+            email: Some(email.to_string()),
+            email_verified,
+            given_name: Some("Ada".to_string()),
+            family_name: Some("Lovelace".to_string()),
+            name: None,
+        }
+    }
+
+    async fn existing_user(users: &InMemoryRepository<UserModel>) -> UserModel {
+        users
+            .create(UserModel {
+                email: "ada@example.com".to_string(),
+                external_identities: vec![ExternalIdentity {
+                    provider: OAuthType::Facebook,
+                    subject: "fb-1".to_string(),
+                    email: None,
+                }],
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn finds_the_user_linked_to_the_identity() {
+        let users = InMemoryRepository::new();
+        let existing = existing_user(&users).await;
+        let user = link_user(
+            &users,
+            OAuthType::Facebook,
+            false,
+            claims("fb-1", "other@example.com", true),
+        )
+        .await
+        .unwrap();
+        assert_eq!(user.id, existing.id);
+        // The same subject at another provider is a different identity.
+        let user = link_user(
+            &users,
+            OAuthType::Google,
+            false,
+            claims("fb-1", "new@example.com", true),
+        )
+        .await
+        .unwrap();
+        assert_ne!(user.id, existing.id);
+    }
+
+    #[actix_web::test]
+    async fn links_a_verified_email_only_when_allowed() {
+        let users = InMemoryRepository::new();
+        let existing = existing_user(&users).await;
+        let rejected = link_user(
+            &users,
+            OAuthType::Google,
+            false,
+            claims("g-1", "Ada@Example.com", true),
+        )
+        .await;
+        assert!(matches!(
+            rejected,
+            Err(Errors::HttpError(HttpErrors::Message(_)))
+        ));
+
+        let user = link_user(
+            &users,
+            OAuthType::Google,
+            true,
+            claims("g-1", "Ada@Example.com", true),
+        )
+        .await
+        .unwrap();
+        assert_eq!(user.id, existing.id);
+        let subjects: Vec<_> = user
+            .external_identities
+            .iter()
+            .map(|identity| identity.subject.as_str())
+            .collect();
+        assert_eq!(subjects, ["fb-1", "g-1"]);
+        assert_eq!(users.count(None).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn creates_a_user_without_a_verified_match() {
+        let users = InMemoryRepository::new();
+        let existing = existing_user(&users).await;
+        // An unverified email neither links nor is stored.
+        let user = link_user(
+            &users,
+            OAuthType::Google,
+            true,
+            claims("g-1", "ada@example.com", false),
+        )
+        .await
+        .unwrap();
+        assert_ne!(user.id, existing.id);
+        assert_eq!(user.email, "");
+
+        let user = link_user(
+            &users,
+            OAuthType::Google,
+            false,
+            claims("g-2", "Grace@Example.com", true),
+        )
+        .await
+        .unwrap();
+        assert_eq!(user.email, "grace@example.com");
+        assert_eq!(user.first_name, "Ada");
+        assert!(matches!(user.roles[..], [Role::User]));
+        assert_eq!(user.external_identities[0].subject, "g-2");
+        assert_eq!(users.count(None).await.unwrap(), 3);
+    }
+
+    // Nothing listens on the JWKS URI, so any fetch fails with an internal error.
+    #[actix_web::test]
+    async fn serves_keys_from_the_cache() {
+        let config = OAuthProviderConfig {
+            jwks_uri: "http://127.0.0.1:9/jwks".to_string(),
+            ..AppConfig::default().oauth.google
+        };
+        let keys: JwkSet = serde_json::from_value(serde_json::json!({"keys": [
+            {"kty": "RSA", "kid": "k1", "alg": "RS256", "n": "AQAB", "e": "AQAB"},
+        ]}))
+        .unwrap();
+        let cache = |fetched_at| {
+            JWKS_CACHE.write().unwrap().insert(
+                config.jwks_uri.clone(),
+                CachedJwks {
+                    keys: keys.clone(),
+                    fetched_at,
+                },
+            );
+        };
+        cache(Instant::now());
+
+        assert_eq!(
+            provider_jwk(&config, Some("k1"))
+                .await
+                .unwrap()
+                .common
+                .key_id,
+            Some("k1".to_string())
+        );
+        assert!(provider_jwk(&config, None).await.is_ok());
+        // Unknown ids do not refetch a set fetched moments ago.
+        assert!(matches!(
+            provider_jwk(&config, Some("k2")).await,
+            Err(Errors::HttpError(HttpErrors::Unauthorized))
+        ));
+
+        cache(Instant::now() - JWKS_REFRESH_INTERVAL);
+        assert!(matches!(
+            provider_jwk(&config, Some("k2")).await,
+            Err(Errors::InternalError(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn takes_each_state_once() {
+        let oauth_states = InMemoryRepository::new();
+        pending(&oauth_states, "first").await;
+        pending(&oauth_states, "second").await;
+
+        let record = take_state(&oauth_states, OAuthType::Google, "first")
+            .await
+            .unwrap();
+        assert_eq!(record.state, "first");
+        assert!(take_state(&oauth_states, OAuthType::Google, "first")
+            .await
+            .is_err());
+
+        // A state presented to the wrong provider is spent all the same.
+        assert!(take_state(&oauth_states, OAuthType::Facebook, "second")
+            .await
+            .is_err());
+        assert!(oauth_states.find(None).await.unwrap().is_empty());
+    }
+}
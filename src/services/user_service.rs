@@ -1,22 +1,404 @@
 use crate::{
-    database::{mongodb::MongoClient},
-    handlers::error_handler::Errors,
-    models::user::*,
+    database::query::{Filter, Update},
+    handlers::error_handler::{Errors, HttpErrors},
+    helpers::{enums::Role, validators::validate_object_id},
+    models::{
+        pagination::{CursorPage, Paginated},
+        user::*,
+    },
+    traits::repository::{ModelStream, Repository},
 };
 
 pub async fn create_user(
-    mongo_client: MongoClient,
+    repository: &impl Repository<UserModel>,
     input: UserCreateModel,
 ) -> Result<UserModel, Errors> {
-    let mut user_model = input.get_user_model();
-    mongo_client
-        .create_one("users", &mut user_model, None, None)
-        .await?;
-    Ok(user_model)
+    repository.create(input.get_user_model()).await
 }
 
-pub async fn get_all_users(mongo_client: MongoClient) -> Result<Vec<UserModel>, Errors> {
-    mongo_client
-        .read_many::<UserModel>("users", None, None)
+pub async fn get_all_users(
+    repository: &impl Repository<UserModel>,
+    with_deleted: bool,
+) -> Result<ModelStream<UserModel>, Errors> {
+    if with_deleted {
+        repository.stream_with_deleted(None).await
+    } else {
+        repository.stream(None).await
+    }
+}
+
+pub async fn list_users(
+    repository: &impl Repository<UserModel>,
+    query: UserListQuery,
+) -> Result<Paginated<UserModel>, Errors> {
+    repository
+        .paginate(
+            Some(query.get_filter_document()?),
+            query.get_sort_document()?,
+            query.page,
+            query.page_size,
+        )
         .await
 }
+
+pub async fn list_users_by_cursor(
+    repository: &impl Repository<UserModel>,
+    query: UserListQuery,
+) -> Result<CursorPage<UserModel>, Errors> {
+    let (sort_field, descending) = query.get_keyset_sort()?;
+    repository
+        .paginate_keyset(
+            Some(query.get_filter_document()?),
+            &sort_field,
+            descending,
+            query.cursor.as_deref(),
+            query.page_size,
+        )
+        .await
+}
+
+pub async fn get_user(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    repository
+        .get_by_id(id)
+        .await?
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+pub async fn update_user(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+    input: UserUpdateModel,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    repository
+        .update(id, input.get_update_document()?)
+        .await?
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+pub async fn update_user_status(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+    input: UserStatusUpdateModel,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    repository
+        .update(id, input.get_update_document()?)
+        .await?
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+pub async fn delete_user(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    repository
+        .delete(id)
+        .await?
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+pub async fn restore_user(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    repository
+        .restore(id)
+        .await?
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+pub async fn purge_user(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    repository
+        .purge(id)
+        .await?
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+pub async fn grant_role(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+    role: Role,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    repository
+        .update(
+            id,
+            Update::new().add_to_set(UserModel::ROLES, role).build()?,
+        )
+        .await?
+        .ok_or(Errors::HttpError(HttpErrors::NotFound))
+}
+
+// Revoking a role also invalidates the user's live tokens, which still carry it. Only
+// a user holding the role is updated, so revoking one they lack keeps their tokens.
+pub async fn revoke_role(
+    repository: &impl Repository<UserModel>,
+    id: &str,
+    role: Role,
+) -> Result<UserModel, Errors> {
+    validate_object_id(id)?;
+    let filter = Filter::and([UserModel::ID.eq(id), UserModel::ROLES.contains(role)]).build()?;
+    let update = Update::new()
+        .pull(UserModel::ROLES, role)
+        .set(UserModel::TOKENS_VALID_AFTER, tokens_cutoff())
+        .build()?;
+    match repository.update_where(filter, update).await? {
+        Some(user) => Ok(user),
+        None => get_user(repository, id).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::AppConfig, database::memory::InMemoryRepository, helpers::enums::UserStatus,
+    };
+    use futures::TryStreamExt;
+
+    fn new_user(first_name: &str, last_name: &str) -> UserCreateModel {
+        UserCreateModel {
+            first_name: first_name.to_string(),
+            last_name: last_name.to_string(),
+        }
+    }
+
+    async fn seeded(names: &[(&str, &str)]) -> (InMemoryRepository<UserModel>, Vec<UserModel>) {
+        let repository = InMemoryRepository::new();
+        let mut users = Vec::new();
+        for (first_name, last_name) in names {
+            users.push(
+                create_user(&repository, new_user(first_name, last_name))
+                    .await
+                    .unwrap(),
+            );
+        }
+        (repository, users)
+    }
+
+    fn is_not_found(result: Result<UserModel, Errors>) -> bool {
+        matches!(result, Err(Errors::HttpError(HttpErrors::NotFound)))
+    }
+
+    #[actix_web::test]
+    async fn creates_and_gets_users() {
+        let (repository, users) = seeded(&[("Ada", "Lovelace")]).await;
+        let user = get_user(&repository, &users[0].id).await.unwrap();
+        assert_eq!(user.first_name, "Ada");
+        assert_eq!(user.roles, vec![Role::User]);
+        assert!(user.created_at > 0);
+        assert!(matches!(
+            get_user(&repository, "not-an-id").await,
+            Err(Errors::HttpError(HttpErrors::Message(_)))
+        ));
+        assert!(is_not_found(
+            get_user(&repository, &mongodb::bson::oid::ObjectId::new().to_hex()).await
+        ));
+    }
+
+    #[actix_web::test]
+    async fn updates_users() {
+        let (repository, users) = seeded(&[("Ada", "Lovelace")]).await;
+        let id = &users[0].id;
+        let update = UserUpdateModel {
+            last_name: Some("$first_name".to_string()),
+            ..Default::default()
+        };
+        let user = update_user(&repository, id, update).await.unwrap();
+        assert_eq!(user.first_name, "Ada");
+        assert_eq!(user.last_name, "$first_name");
+        assert!(update_user(&repository, id, UserUpdateModel::default())
+            .await
+            .is_err());
+
+        let status = UserStatusUpdateModel {
+            user_status: UserStatus::Inactive,
+        };
+        let user = update_user_status(&repository, id, status).await.unwrap();
+        assert!(matches!(user.user_status, UserStatus::Inactive));
+        assert!(user.tokens_valid_after.is_some());
+    }
+
+    #[actix_web::test]
+    async fn soft_deletes_restores_and_purges_users() {
+        let (repository, users) = seeded(&[("Ada", "Lovelace"), ("Grace", "Hopper")]).await;
+        let id = &users[0].id;
+        let deleted = delete_user(&repository, id).await.unwrap();
+        assert!(deleted.is_deleted);
+        assert!(deleted.deleted_at.is_some());
+        assert!(is_not_found(get_user(&repository, id).await));
+        assert!(is_not_found(delete_user(&repository, id).await));
+
+        let visible: Vec<_> = get_all_users(&repository, false)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(visible.len(), 1);
+        let all: Vec<_> = get_all_users(&repository, true)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 2);
+
+        let restored = restore_user(&repository, id).await.unwrap();
+        assert!(!restored.is_deleted);
+        assert!(restored.deleted_at.is_none());
+        assert!(is_not_found(restore_user(&repository, id).await));
+
+        purge_user(&repository, id).await.unwrap();
+        assert!(is_not_found(get_user(&repository, id).await));
+        assert!(is_not_found(restore_user(&repository, id).await));
+    }
+
+    #[actix_web::test]
+    async fn paginates_filters_and_sorts_users() {
+        let (repository, users) = seeded(&[
+            ("Ada", "Lovelace"),
+            ("Grace", "Hopper"),
+            ("Alan", "Turing"),
+            ("Adele", "Goldberg"),
+        ])
+        .await;
+        delete_user(&repository, &users[3].id).await.unwrap();
+
+        let query = UserListQuery {
+            page: Some(1),
+            page_size: Some(2),
+            sort: Some("-first_name".to_string()),
+            ..Default::default()
+        };
+        let page = list_users(&repository, query).await.unwrap();
+        let names: Vec<_> = page
+            .data
+            .iter()
+            .map(|user| user.first_name.as_str())
+            .collect();
+        assert_eq!(names, ["Grace", "Alan"]);
+        assert_eq!(page.metadata.total_records, 3);
+        assert!(page.metadata.has_next_page);
+
+        let query = UserListQuery {
+            page: Some(2),
+            page_size: Some(2),
+            sort: Some("-first_name".to_string()),
+            ..Default::default()
+        };
+        let page = list_users(&repository, query).await.unwrap();
+        assert_eq!(page.data.len(), 1);
+        assert!(!page.metadata.has_next_page);
+
+        let query = UserListQuery {
+            name: Some("a".to_string()),
+            sort: Some("last_name".to_string()),
+            ..Default::default()
+        };
+        let page = list_users(&repository, query).await.unwrap();
+        let names: Vec<_> = page
+            .data
+            .iter()
+            .map(|user| user.last_name.as_str())
+            .collect();
+        assert_eq!(names, ["Hopper", "Lovelace", "Turing"]);
+
+        let query = UserListQuery {
+            sort: Some("password_hash".to_string()),
+            ..Default::default()
+        };
+        assert!(list_users(&repository, query).await.is_err());
+
+        let query = UserListQuery {
+            page: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(list_users(&repository, query).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn pages_users_by_cursor() {
+        AppConfig::init_for_tests();
+        let (repository, _) =
+            seeded(&[("Ada", "Lovelace"), ("Grace", "Hopper"), ("Alan", "Turing")]).await;
+        let query = |cursor: Option<String>| UserListQuery {
+            page_size: Some(2),
+            sort: Some("first_name".to_string()),
+            cursor,
+            ..Default::default()
+        };
+        let first = list_users_by_cursor(&repository, query(None))
+            .await
+            .unwrap();
+        let names: Vec<_> = first
+            .data
+            .iter()
+            .map(|user| user.first_name.as_str())
+            .collect();
+        assert_eq!(names, ["Ada", "Alan"]);
+        assert!(first.prev_cursor.is_none());
+
+        let second = list_users_by_cursor(&repository, query(first.next_cursor))
+            .await
+            .unwrap();
+        let names: Vec<_> = second
+            .data
+            .iter()
+            .map(|user| user.first_name.as_str())
+            .collect();
+        assert_eq!(names, ["Grace"]);
+        assert!(second.next_cursor.is_none());
+
+        let back = list_users_by_cursor(&repository, query(second.prev_cursor))
+            .await
+            .unwrap();
+        let names: Vec<_> = back
+            .data
+            .iter()
+            .map(|user| user.first_name.as_str())
+            .collect();
+        assert_eq!(names, ["Ada", "Alan"]);
+
+        let forged = query(Some("e30.e30".to_string()));
+        assert!(list_users_by_cursor(&repository, forged).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn grants_and_revokes_roles() {
+        let (repository, users) = seeded(&[("Ada", "Lovelace")]).await;
+        let id = &users[0].id;
+        let user = grant_role(&repository, id, Role::Admin).await.unwrap();
+        assert_eq!(user.roles, vec![Role::User, Role::Admin]);
+        assert!(user.tokens_valid_after.is_none());
+        let user = grant_role(&repository, id, Role::Admin).await.unwrap();
+        assert_eq!(user.roles, vec![Role::User, Role::Admin]);
+        let user = revoke_role(&repository, id, Role::Admin).await.unwrap();
+        assert_eq!(user.roles, vec![Role::User]);
+        assert!(user.tokens_valid_after.is_some());
+
+        let (repository, users) = seeded(&[("Grace", "Hopper")]).await;
+        let id = &users[0].id;
+        let user = revoke_role(&repository, id, Role::Admin).await.unwrap();
+        assert_eq!(user.roles, vec![Role::User]);
+        assert!(user.tokens_valid_after.is_none());
+        let missing = mongodb::bson::oid::ObjectId::new().to_hex();
+        assert!(is_not_found(
+            grant_role(&repository, &missing, Role::Admin).await
+        ));
+        assert!(is_not_found(
+            revoke_role(&repository, &missing, Role::Admin).await
+        ));
+    }
+}
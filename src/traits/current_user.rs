@@ -0,0 +1,100 @@
+use super::{jwt::JwtToken, model::ModelTrait};
+use crate::{
+    database::mongodb::MongoClient,
+    handlers::error_handler::{Errors, HttpErrors},
+    models::user::UserModel,
+};
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use futures::future::LocalBoxFuture;
+
+// The authenticated user. The `JwtToken` extractor already loads the user while
+// checking revocation and caches it in the request extensions, so this normally
+// costs no extra query. Inactive users never get here, their tokens are rejected
+// with 401 like any other revoked token.
+pub struct CurrentUser {
+    pub user: UserModel,
+    pub token: JwtToken,
+}
+
+async fn load_user(req: &HttpRequest, user_id: &str) -> Result<Option<UserModel>, Errors> {
+    if let Some(user) = req.extensions().get::<UserModel>() {
+        if user.id == user_id {
+            return Ok(Some(user.clone()));
+        }
+    }
+    let client = req
+        .app_data::<web::Data<MongoClient>>()
+        .cloned()
+        .ok_or(Errors::InternalError(
+            "Mongo client is not configured".to_string(),
+        ))?;
+    let user = client
+        .read_one::<UserModel>(
+            UserModel::COLLECTION_NAME,
+            UserModel::ID.eq(user_id).build()?,
+            None,
+            None,
+        )
+        .await?;
+    if let Some(user) = &user {
+        req.extensions_mut().insert(user.clone());
+    }
+    Ok(user)
+}
+
+impl FromRequest for CurrentUser {
+    type Error = Errors;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let token_future = JwtToken::from_request(req, payload);
+        let req = req.clone();
+
+        Box::pin(async move {
+            let token = token_future.await?;
+            let user = load_user(&req, token.user_id())
+                .await?
+                .filter(|user| !user.is_deleted)
+                .ok_or(Errors::HttpError(HttpErrors::Unauthorized))?;
+            Ok(Self { user, token })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{enums::JwtTokenType, jwt_keys::JwtKeys};
+    use actix_web::{http::header, test::TestRequest};
+
+    fn request(token: &JwtToken) -> HttpRequest {
+        let bearer = format!("Bearer {}", token.encode_self().unwrap());
+        TestRequest::default()
+            .insert_header((header::AUTHORIZATION, bearer))
+            .to_http_request()
+    }
+
+    // No Mongo client is configured, so any lookup fails.
+    #[actix_web::test]
+    async fn reuses_the_token_checked_earlier_in_the_request() {
+        JwtKeys::init_for_tests();
+        let token = JwtToken::new("user-1", JwtTokenType::Access, "jti-1", "family-1");
+        let req = request(&token);
+        req.extensions_mut().insert(token.clone());
+        req.extensions_mut().insert(UserModel {
+            id: "user-1".to_string(),
+            ..Default::default()
+        });
+        let current = CurrentUser::extract(&req).await.unwrap();
+        assert_eq!(current.user.id, "user-1");
+        assert_eq!(current.token.jti, "jti-1");
+
+        let other = JwtToken::new("user-1", JwtTokenType::Access, "jti-2", "family-1");
+        let req = request(&other);
+        req.extensions_mut().insert(token);
+        assert!(matches!(
+            CurrentUser::extract(&req).await,
+            Err(Errors::InternalError(_))
+        ));
+    }
+}
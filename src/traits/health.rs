@@ -0,0 +1,10 @@
+use async_trait::async_trait;
+use serde_json::Value;
+
+// A dependency reported by `/health/ready`. `check` returns details to include
+// in the report, or the reason the dependency is down.
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn check(&self) -> Result<Value, String>;
+}
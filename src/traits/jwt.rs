@@ -1,93 +1,175 @@
 use crate::{
+    config::AppConfig,
+    database::mongodb::MongoClient,
     handlers::error_handler::{Errors, HttpErrors},
-    helpers::enums,
+    helpers::{enums, jwt_keys::JwtKeys},
+    services::auth_service,
 };
-use actix_web::{http, FromRequest};
+use actix_web::{dev::Payload, http, web, FromRequest, HttpMessage, HttpRequest};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use futures::future::LocalBoxFuture;
+use jsonwebtoken::{
+    decode, decode_header, encode, errors::ErrorKind, Algorithm, Header, Validation,
+};
 use serde::{Deserialize, Serialize};
-use std::future::{ready, Ready};
-
-impl Default for enums::JwtTokenType {
-    fn default() -> Self {
-        Self::Access
-    }
-}
 
+// Registered claims (RFC 7519) plus the token type and the refresh family it belongs to.
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct JwtToken {
-    pub user_id: String,
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: u64,
+    // `iat` in milliseconds, so a token issued right after a cutoff in the same
+    // second still counts as issued after it.
+    #[serde(default)]
+    pub iat_ms: u64,
+    pub nbf: u64,
+    pub exp: u64,
+    pub jti: String,
     pub token_type: enums::JwtTokenType,
-    pub expiry: u64,
+    #[serde(default)]
+    pub family_id: String,
+    #[serde(default)]
+    pub roles: Vec<enums::Role>,
+    #[serde(default)]
+    pub permissions: Vec<enums::Permission>,
 }
 
 impl JwtToken {
-    pub fn create_fresh_pair(user_id: impl Into<String>) -> Result<(String, String), Errors> {
-        let access_token = Self {
-            user_id: user_id.into().clone(),
-            token_type: enums::JwtTokenType::Refresh,
-            expiry: (Utc::now() + Duration::days(1)).timestamp() as u64,
+    pub fn new(
+        user_id: impl Into<String>,
+        token_type: enums::JwtTokenType,
+        jti: impl Into<String>,
+        family_id: impl Into<String>,
+    ) -> Self {
+        let config = &AppConfig::get().jwt;
+        let ttl = match token_type {
+            enums::JwtTokenType::Access => config.access_token_ttl_seconds,
+            enums::JwtTokenType::Refresh => config.refresh_token_ttl_seconds,
         };
-        Ok((access_token.encode_self()?, access_token.encode_self()?))
+        let now = Utc::now();
+        Self {
+            sub: user_id.into(),
+            iss: config.issuer.clone(),
+            aud: config.audience.clone(),
+            iat: now.timestamp() as u64,
+            iat_ms: now.timestamp_millis() as u64,
+            nbf: now.timestamp() as u64,
+            exp: (now + Duration::seconds(ttl)).timestamp() as u64,
+            jti: jti.into(),
+            token_type,
+            family_id: family_id.into(),
+            roles: Vec::new(),
+            permissions: Vec::new(),
+        }
     }
-    fn encode_self(&self) -> Result<String, Errors> {
-        let secret = String::default();
-        encode(
-            &Header::default(),
-            &self,
-            &EncodingKey::from_secret(secret.as_bytes()),
-        )
-        .map_err(|error| Errors::InternalError(error.to_string()))
+    pub fn has_role(&self, role: enums::Role) -> bool {
+        self.roles.contains(&role)
     }
-    pub fn decode(token: String) -> Result<Self, Errors> {
-        let secret = String::default();
-        let token_data_result = decode::<Self>(
-            token.as_str(),
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        );
-        match token_data_result {
-            Ok(token_data) => {
-                let jwt_token = token_data.claims;
-                if jwt_token.has_expired() {
-                    Err(Errors::HttpError(HttpErrors::Unauthorized))
-                } else {
-                    Ok(jwt_token)
-                }
-            }
-            Err(_) => Err(Errors::HttpError(HttpErrors::BadRequest)),
+    pub fn has_permission(&self, permission: enums::Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+    pub fn user_id(&self) -> &str {
+        &self.sub
+    }
+    // Tokens without `iat_ms` count from the start of the second they were issued in.
+    pub fn issued_at_millis(&self) -> u64 {
+        if self.iat_ms > 0 {
+            self.iat_ms
+        } else {
+            self.iat * 1000
         }
     }
-    pub fn has_expired(&self) -> bool {
-        self.expiry < Utc::now().timestamp() as u64
+    pub fn encode_self(&self) -> Result<String, Errors> {
+        let keys = JwtKeys::get()?;
+        let mut header = Header::new(keys.signing.algorithm);
+        header.kid = Some(keys.signing.kid.clone());
+        encode(&header, &self, &keys.signing.encoding_key)
+            .map_err(|error| Errors::InternalError(error.to_string()))
+    }
+    fn validation(algorithm: Algorithm) -> Validation {
+        let config = &AppConfig::get().jwt;
+        let mut validation = Validation::new(algorithm);
+        validation.set_required_spec_claims(&["exp", "nbf", "iat", "sub", "iss", "aud", "jti"]);
+        validation.set_issuer(&[config.issuer.as_str()]);
+        validation.set_audience(&[config.audience.as_str()]);
+        validation.validate_nbf = true;
+        validation.leeway = config.leeway_seconds;
+        validation
+    }
+    pub fn decode(token: String) -> Result<Self, Errors> {
+        let keys = JwtKeys::get()?;
+        let invalid_token = || Errors::HttpError(HttpErrors::InvalidToken);
+        let header = decode_header(token.as_str()).map_err(|_| invalid_token())?;
+        let key = keys
+            .verification_key(header.kid.as_deref())
+            .filter(|key| key.algorithm == header.alg)
+            .ok_or_else(invalid_token)?;
+        decode::<Self>(
+            token.as_str(),
+            &key.decoding_key,
+            &Self::validation(key.algorithm),
+        )
+        .map(|token_data| token_data.claims)
+        .map_err(|error| match error.kind() {
+            ErrorKind::ExpiredSignature => Errors::HttpError(HttpErrors::TokenExpired),
+            _ => invalid_token(),
+        })
     }
 }
-impl FromRequest for JwtToken {
-    type Error = Errors;
-    type Future = Ready<Result<Self, Self::Error>>;
-
-    fn from_request(req: &actix_web::HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+impl JwtToken {
+    fn from_authorization_header(req: &HttpRequest) -> Result<Self, Errors> {
         let header = req.headers().get(http::header::AUTHORIZATION);
         let token = match header {
-            Some(token_value) => token_value.to_str().unwrap(),
+            Some(token_value) => token_value.to_str().unwrap_or_default(),
             None => "",
         };
         let token = match token.strip_prefix("Bearer ") {
             Some(token) => token.to_string(),
-            None => return ready(Err(Errors::HttpError(HttpErrors::Unauthorized))),
+            None => return Err(Errors::HttpError(HttpErrors::Unauthorized)),
         };
+        match Self::decode(token)? {
+            decoded_token if decoded_token.token_type == enums::JwtTokenType::Access => {
+                Ok(decoded_token)
+            }
+            _ => Err(Errors::HttpError(HttpErrors::InvalidToken)),
+        }
+    }
+}
 
-        let decoded_token_result = match Self::decode(token) {
-            Ok(decoded_token) => decoded_token,
-            Err(_) => return ready(Err(Errors::HttpError(HttpErrors::Unauthorized))),
-        };
-        /*
-        let client = match req.app_data::<web::Data<MongoClient>>() {
-            Some(client) => client.get_ref().clone(),
-            None => return ready(Err(Errors::HttpError(HttpErrors::BadRequest))),
-        };
-        */
+impl FromRequest for JwtToken {
+    type Error = Errors;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let decoded_token_result = Self::from_authorization_header(req);
+        let client = req.app_data::<web::Data<MongoClient>>().cloned();
+        let req = req.clone();
 
-        ready(Ok(decoded_token_result))
+        Box::pin(async move {
+            let decoded_token = decoded_token_result?;
+            // Extractors stacked on one handler, e.g. `JwtToken` and `CurrentUser`,
+            // check the token against the database only once per request.
+            let verified = req
+                .extensions()
+                .get::<JwtToken>()
+                .is_some_and(|verified| verified.jti == decoded_token.jti);
+            if verified {
+                return Ok(decoded_token);
+            }
+            let client = client.ok_or(Errors::InternalError(
+                "Mongo client is not configured".to_string(),
+            ))?;
+            let user = auth_service::ensure_token_active(
+                client.get_ref(),
+                client.get_ref(),
+                &decoded_token,
+            )
+            .await?;
+            req.extensions_mut().insert(user);
+            req.extensions_mut().insert(decoded_token.clone());
+            Ok(decoded_token)
+        })
     }
 }
@@ -1,2 +1,7 @@
+pub mod current_user;
+pub mod health;
 pub mod jwt;
 pub mod model;
+pub mod rbac;
+pub mod repository;
+pub mod validated_json;
@@ -1,5 +1,18 @@
+use mongodb::IndexModel;
+
 pub trait ModelTrait {
+    const COLLECTION_NAME: &'static str;
+    // Soft deleted models are expected to carry `is_deleted` and `deleted_at` fields.
+    const SOFT_DELETE: bool = false;
+
+    fn get_id(&self) -> &str;
     fn set_id(&mut self, id: String);
     fn set_created_at(&mut self, created_at: u64);
     fn set_updated_at(&mut self, updated_at: u64);
+
+    // Indexes kept in sync with the collection at startup, see `database::indexes`.
+    // Unnamed ones are named after their keys, e.g. `email_1`.
+    fn indexes() -> Vec<IndexModel> {
+        Vec::new()
+    }
 }
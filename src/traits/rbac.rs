@@ -0,0 +1,90 @@
+use super::jwt::JwtToken;
+use crate::{
+    handlers::error_handler::{Errors, HttpErrors},
+    helpers::enums::{Permission, Role},
+};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use std::marker::PhantomData;
+
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct Admin;
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+pub struct ReadUsers;
+
+impl RequiredPermission for ReadUsers {
+    const PERMISSION: Permission = Permission::ReadUsers;
+}
+
+pub struct ManageUsers;
+
+impl RequiredPermission for ManageUsers {
+    const PERMISSION: Permission = Permission::ManageUsers;
+}
+
+pub struct ManageRoles;
+
+impl RequiredPermission for ManageRoles {
+    const PERMISSION: Permission = Permission::ManageRoles;
+}
+
+// Authenticates like `JwtToken` and then answers 403 unless the token carries `R::ROLE`.
+pub struct RequireRole<R> {
+    pub token: JwtToken,
+    role: PhantomData<R>,
+}
+
+impl<R: RequiredRole + 'static> FromRequest for RequireRole<R> {
+    type Error = Errors;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let token_future = JwtToken::from_request(req, payload);
+        Box::pin(async move {
+            let token = token_future.await?;
+            if !token.has_role(R::ROLE) {
+                return Err(Errors::HttpError(HttpErrors::Forbidden));
+            }
+            Ok(Self {
+                token,
+                role: PhantomData,
+            })
+        })
+    }
+}
+
+// Authenticates like `JwtToken` and then answers 403 unless the token carries `P::PERMISSION`.
+pub struct RequirePermission<P> {
+    pub token: JwtToken,
+    permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission + 'static> FromRequest for RequirePermission<P> {
+    type Error = Errors;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let token_future = JwtToken::from_request(req, payload);
+        Box::pin(async move {
+            let token = token_future.await?;
+            if !token.has_permission(P::PERMISSION) {
+                return Err(Errors::HttpError(HttpErrors::Forbidden));
+            }
+            Ok(Self {
+                token,
+                permission: PhantomData,
+            })
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use super::model::ModelTrait;
+use crate::{
+    handlers::error_handler::Errors,
+    models::pagination::{CursorPage, Paginated},
+};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use mongodb::bson::Document;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub trait RepositoryModel:
+    ModelTrait + DeserializeOwned + Serialize + Clone + Send + Sync + Unpin + 'static
+{
+}
+
+impl<M> RepositoryModel for M where
+    M: ModelTrait + DeserializeOwned + Serialize + Clone + Send + Sync + Unpin + 'static
+{
+}
+
+pub type ModelStream<M> = BoxStream<'static, Result<M, Errors>>;
+
+// Backend agnostic persistence for a single model. `update` expects an update
+// document made of operators (`{"$set": {...}}`, `{"$unset": {...}}`).
+// Soft deleted models are hidden from every read except the `_with_deleted` ones.
+#[async_trait]
+pub trait Repository<M: RepositoryModel>: Send + Sync {
+    async fn create(&self, model: M) -> Result<M, Errors>;
+    async fn get_by_id(&self, id: &str) -> Result<Option<M>, Errors>;
+    async fn find(&self, filter: Option<Document>) -> Result<Vec<M>, Errors>;
+    async fn update(&self, id: &str, update: Document) -> Result<Option<M>, Errors>;
+    // Updates the first model matching `filter`, which makes conditional writes atomic.
+    async fn update_where(&self, filter: Document, update: Document) -> Result<Option<M>, Errors>;
+    async fn delete(&self, id: &str) -> Result<Option<M>, Errors>;
+    async fn find_with_deleted(&self, filter: Option<Document>) -> Result<Vec<M>, Errors>;
+    // Like `find`, but yields models one at a time instead of buffering them all.
+    async fn stream(&self, filter: Option<Document>) -> Result<ModelStream<M>, Errors>;
+    async fn stream_with_deleted(&self, filter: Option<Document>)
+        -> Result<ModelStream<M>, Errors>;
+    async fn restore(&self, id: &str) -> Result<Option<M>, Errors>;
+    async fn purge(&self, id: &str) -> Result<Option<M>, Errors>;
+    // Removes the first model matching `filter` and returns it, which makes single use
+    // records atomic.
+    async fn purge_where(&self, filter: Document) -> Result<Option<M>, Errors>;
+    async fn count(&self, filter: Option<Document>) -> Result<u64, Errors>;
+    async fn paginate(
+        &self,
+        filter: Option<Document>,
+        sort: Option<Document>,
+        page: Option<u64>,
+        page_size: Option<u64>,
+    ) -> Result<Paginated<M>, Errors>;
+    async fn paginate_keyset(
+        &self,
+        filter: Option<Document>,
+        sort_field: &str,
+        descending: bool,
+        cursor: Option<&str>,
+        page_size: Option<u64>,
+    ) -> Result<CursorPage<M>, Errors>;
+}
@@ -0,0 +1,41 @@
+use crate::helpers::validators::validation_error;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use std::ops::Deref;
+use validator::Validate;
+
+// `web::Json` that also runs the model's `Validate` rules, rejecting the request
+// with a 422 listing every invalid field.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidatedJson<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json_future = web::Json::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let value = json_future.await?.into_inner();
+            value
+                .validate()
+                .map_err(|errors| validation_error(&errors))?;
+            Ok(Self(value))
+        })
+    }
+}